@@ -0,0 +1,224 @@
+//! Fingerprint stylesheets and scripts so that browsers fetch them again after a deploy
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::errors::ChabloError;
+use crate::models::percent_encode;
+use crate::writer::Outputs;
+
+/// Output path of the manifest, relative to `public/`
+pub const ASSET_MANIFEST_PATH: &str = "asset-manifest.json";
+
+/// Extensions of the assets given a fingerprinted copy
+const FINGERPRINTED_EXTENSIONS: [&str; 2] = ["css", "js"];
+
+/// Length of the content hash in fingerprinted filenames
+const FINGERPRINT_LENGTH: usize = 8;
+
+/// Map from the path of an asset to its fingerprinted copy, e.g. `css/main.css` to
+/// `css/main.3f2a1c9e.css`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct AssetManifest(pub BTreeMap<String, String>);
+
+impl AssetManifest {
+    /// URL of the fingerprinted copy of `path`, or of `path` itself when it has none
+    pub fn asset_url(&self, path: &str) -> String {
+        let path = path.trim_start_matches("./").trim_start_matches('/');
+        let path = self.0.get(path).map_or(path, String::as_str);
+
+        format!("/{}", percent_encode(path))
+    }
+
+    /// Write down the manifest as JSON
+    pub fn write(&self, output_dir: &Path, outputs: &mut Outputs) -> Result<(), ChabloError> {
+        let json = serde_json::to_string_pretty(&self.0)?;
+
+        outputs.write(
+            json,
+            &output_dir.join(ASSET_MANIFEST_PATH).to_string_lossy(),
+        )
+    }
+}
+
+/// Copy every stylesheet and script written under `output_dir` by this build to a name
+/// containing the hash of its content. The originals are kept for pages linking them
+/// directly, while files left by earlier builds are ignored.
+pub fn fingerprint_assets(
+    output_dir: &Path,
+    outputs: &mut Outputs,
+) -> Result<AssetManifest, ChabloError> {
+    let mut manifest = AssetManifest::default();
+    if !output_dir.is_dir() {
+        return Ok(manifest);
+    }
+    let output_dir = fs::canonicalize(output_dir)?;
+    let assets: Vec<PathBuf> = outputs
+        .paths()
+        .filter(|path| path.starts_with(&output_dir) && is_asset(path))
+        .cloned()
+        .collect();
+
+    for path in assets {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        let content = fs::read(&path)?;
+        let hash = format!("{:x}", Sha256::digest(&content));
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let fingerprinted = path.with_file_name(format!(
+            "{stem}.{}.{extension}",
+            &hash[..FINGERPRINT_LENGTH]
+        ));
+        outputs.write(&content, &fingerprinted.to_string_lossy())?;
+
+        manifest.0.insert(
+            relative_url(&path, &output_dir),
+            relative_url(&fingerprinted, &output_dir),
+        );
+    }
+
+    Ok(manifest)
+}
+
+/// Whether the file is a stylesheet or script other than a fingerprinted copy
+fn is_asset(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    FINGERPRINTED_EXTENSIONS.contains(&extension) && !is_fingerprinted(&file_name)
+}
+
+/// Whether the file is a copy made by an earlier build
+fn is_fingerprinted(file_name: &str) -> bool {
+    static FINGERPRINTED: OnceLock<Regex> = OnceLock::new();
+    FINGERPRINTED
+        .get_or_init(|| {
+            Regex::new(&format!(
+                r"\.[0-9a-f]{{{FINGERPRINT_LENGTH}}}\.({})$",
+                FINGERPRINTED_EXTENSIONS.join("|")
+            ))
+            .expect("valid regex")
+        })
+        .is_match(file_name)
+}
+
+fn relative_url(path: &Path, output_dir: &Path) -> String {
+    let relative = path.strip_prefix(output_dir).unwrap_or(path);
+
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+    use pretty_assertions::assert_eq;
+
+    /// Outputs of a build writing `files` under `dir`
+    fn build(dir: &Path, files: &[(&str, &str)]) -> Outputs {
+        let mut outputs = Outputs::default();
+        for (file, content) in files {
+            outputs
+                .write(content, &dir.join(file).to_string_lossy())
+                .unwrap();
+        }
+
+        outputs
+    }
+
+    #[test]
+    fn test_fingerprint_assets() {
+        let dir = temp_dir("fingerprint_assets");
+        let dir = dir.path();
+        let files = [
+            ("css/main.css", "body { color: red; }"),
+            ("app.js", "alert(1);"),
+            ("index.html", "<p></p>"),
+        ];
+
+        let first = fingerprint_assets(dir, &mut build(dir, &files)).unwrap();
+        // Copies from the previous build are not fingerprinted again
+        let second = fingerprint_assets(dir, &mut build(dir, &files)).unwrap();
+        let fingerprinted = &first.0["css/main.css"];
+        let content = fs::read_to_string(dir.join(fingerprinted)).unwrap();
+        let mut files: Vec<_> = fs::read_dir(dir.join("css"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        files.sort();
+
+        assert_eq!(first, second);
+        assert_eq!(first.0.len(), 2);
+        assert!(first.0["app.js"].starts_with("app."));
+        assert!(is_fingerprinted(fingerprinted));
+        assert!(fingerprinted.starts_with("css/main."));
+        assert_eq!(content, "body { color: red; }");
+        assert_eq!(files.len(), 2);
+    }
+
+    #[test]
+    fn test_fingerprint_changes_with_content() {
+        let dir = temp_dir("fingerprint_changes");
+        let dir = dir.path();
+        let before = fingerprint_assets(dir, &mut build(dir, &[("main.css", "a {}")])).unwrap();
+        let after = fingerprint_assets(dir, &mut build(dir, &[("main.css", "b {}")])).unwrap();
+
+        assert_ne!(before.0["main.css"], after.0["main.css"]);
+    }
+
+    #[test]
+    fn test_fingerprint_assets_of_this_build_only() {
+        let dir = temp_dir("fingerprint_stale");
+        let dir = dir.path();
+        // Left by an earlier build, and removed as stale after this one
+        fs::write(dir.join("old.css"), "a {}").unwrap();
+        let mut outputs = build(dir, &[("main.css", "b {}")]);
+
+        let result = fingerprint_assets(dir, &mut outputs).unwrap();
+        let fingerprinted = outputs.contains(&dir.join(&result.0["main.css"]));
+
+        assert_eq!(result.0.keys().collect::<Vec<_>>(), vec!["main.css"]);
+        assert!(fingerprinted);
+    }
+
+    #[test]
+    fn test_asset_url() {
+        let manifest = AssetManifest(
+            [(
+                "css/main.css".to_string(),
+                "css/main.3f2a1c9e.css".to_string(),
+            )]
+            .into(),
+        );
+
+        assert_eq!(manifest.asset_url("css/main.css"), "/css/main.3f2a1c9e.css");
+        assert_eq!(
+            manifest.asset_url("/css/main.css"),
+            "/css/main.3f2a1c9e.css"
+        );
+        assert_eq!(manifest.asset_url("css/unknown.css"), "/css/unknown.css");
+    }
+
+    #[test]
+    fn test_write_manifest() {
+        let dir = temp_dir("asset_manifest");
+        let manifest =
+            AssetManifest([("app.js".to_string(), "app.0123abcd.js".to_string())].into());
+
+        manifest.write(dir.path(), &mut Outputs::default()).unwrap();
+        let result = fs::read_to_string(dir.path().join(ASSET_MANIFEST_PATH)).unwrap();
+
+        assert_eq!(result, "{\n  \"app.js\": \"app.0123abcd.js\"\n}");
+    }
+}
@@ -1,61 +1,426 @@
 //! Build static files
-use std::path::PathBuf;
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
+use chrono::Datelike;
 use glob::glob;
-
-use crate::models::{Article, TopPage, UpdatedTitle};
-use crate::parser::parse;
-use crate::writer::write;
+use log::info;
+
+use crate::assets::fingerprint_assets;
+use crate::bundle::{copy_bundle, rewrite_bundle_links};
+use crate::bundler::build_bundle;
+use crate::compress::precompress;
+use crate::config::{Config, LinkCheck, MarkdownOptions};
+use crate::diagram::Diagrams;
+use crate::highlight::{highlight_css, HIGHLIGHT_CSS_PATH};
+use crate::images::{process_images, IMAGE_CACHE_DIR};
+use crate::links::{check_links, link_anchors_to, BrokenLink, InternalLinks};
+use crate::minify::minify_html;
+use crate::models::{Article, Feed, Page, Section, Site, SortOrder, TopPage, UpdatedTitle};
+use crate::og_image::generate_og_image;
+use crate::parser::{page_path, parse, parse_page};
+use crate::shortcode::Shortcodes;
+use crate::theme::Theme;
+use crate::writer::{Outputs, COPY_CACHE_PATH};
 use crate::Generator;
 
 use crate::errors::ChabloError;
 
+/// Directory of the generated site
+pub const OUTPUT_DIR: &str = "public";
+
+/// Directory of standalone pages, rendered outside the diary timeline
+const PAGES_DIR: &str = "pages";
+
+/// Files copied verbatim into the output, such as stylesheets and images
+const STATIC_DIR: &str = "static";
+
+/// Whether the site is built for deployment or for `chablo serve`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BuildMode {
+    /// Minified assets
+    #[default]
+    Production,
+    /// Readable assets with source maps
+    Development,
+}
+
 pub fn build() -> Result<(), ChabloError> {
-    let path = "diary/**/*.md";
+    build_with(BuildMode::Production)
+}
+
+pub fn build_with(mode: BuildMode) -> Result<(), ChabloError> {
+    let config = Config::load()?;
+    let mut theme = Theme::load(config.theme.as_deref())?;
+    let shortcodes = Shortcodes::new(&theme);
+    let diagrams = Diagrams::default();
+    let mut outputs = Outputs::with_copy_cache(Path::new(COPY_CACHE_PATH));
+    // Static files first, so that generated files and processed images take precedence
+    theme.copy_static(OUTPUT_DIR, &mut outputs)?;
+    copy_static(STATIC_DIR, OUTPUT_DIR, &mut outputs)?;
+    write_output(&highlight_css()?, HIGHLIGHT_CSS_PATH, &mut outputs)?;
+    for bundle in &config.bundles {
+        build_bundle(bundle, Path::new(OUTPUT_DIR), mode, &mut outputs)?;
+    }
+    // Assets are complete at this point, and templates link their fingerprinted copies
+    let assets = fingerprint_assets(Path::new(OUTPUT_DIR), &mut outputs)?;
+    assets.write(Path::new(OUTPUT_DIR), &mut outputs)?;
+    theme.set_assets(assets.clone());
+
+    // Prepare articles of every section to build static website
+    let mut articles: Vec<Article> = vec![];
+    for section in &config.sections {
+        articles.extend(collect_section(
+            section,
+            &config.markdown,
+            &shortcodes,
+            &diagrams,
+        )?);
+    }
+    let mut site = Site::new(&config, &articles);
+    site.assets = assets;
+    let links = internal_links(&articles, PAGES_DIR)?;
+    for article in articles.iter_mut() {
+        article.body = links.resolve(&article.body);
+        article.excerpt = link_anchors_to(&links.resolve(&article.excerpt), &article.path);
+    }
+    attach_og_images(&mut articles, &site, &mut outputs)?;
+    for article in articles.iter_mut() {
+        copy_bundle(article, Path::new(OUTPUT_DIR), &mut outputs)?;
+        article.body = process_images(
+            &article.body,
+            Path::new(OUTPUT_DIR),
+            Path::new(IMAGE_CACHE_DIR),
+            &config.images,
+            &mut outputs,
+        )?;
+        article.excerpt = process_images(
+            &article.excerpt,
+            Path::new(OUTPUT_DIR),
+            Path::new(IMAGE_CACHE_DIR),
+            &config.images,
+            &mut outputs,
+        )?;
+    }
+    build_articles(
+        articles.clone(),
+        &site,
+        &theme,
+        config.minify_html,
+        &mut outputs,
+    )?;
+    for section in &config.sections {
+        build_section(
+            section,
+            &articles,
+            &site,
+            &theme,
+            config.minify_html,
+            &mut outputs,
+        )?;
+    }
+    let pages = collect_pages(PAGES_DIR, &config.markdown, &shortcodes, &diagrams)?;
+    build_pages(
+        pages,
+        &links,
+        &site,
+        &theme,
+        config.minify_html,
+        &mut outputs,
+    )?;
+
+    precompress(Path::new(OUTPUT_DIR), &mut outputs, &config.compression)?;
+
+    for stale in remove_stale_outputs(Path::new(OUTPUT_DIR), &outputs)? {
+        info!("Removed stale output {}", stale.display());
+    }
+    outputs.save_copies(Path::new(COPY_CACHE_PATH))?;
+
+    report_broken_links(&check_links(Path::new(OUTPUT_DIR))?, config.broken_links)
+}
+
+/// Remove the output directory, refusing to touch anything outside the project
+pub fn clean() -> Result<(), ChabloError> {
+    clean_dir(Path::new(OUTPUT_DIR), &std::env::current_dir()?)
+}
+
+fn clean_dir(dir: &Path, project_dir: &Path) -> Result<(), ChabloError> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    let dir = fs::canonicalize(dir)?;
+    let project_dir = fs::canonicalize(project_dir)?;
+    if dir == project_dir || !dir.starts_with(&project_dir) {
+        return Err(ChabloError::UnsafeClean(dir.display().to_string()));
+    }
+
+    fs::remove_dir_all(dir)?;
+
+    Ok(())
+}
 
-    // Prepare articles to build static website
-    let articles = collect_articles(path)?;
-    build_articles(articles.clone())?;
-    let toppage = TopPage { articles };
-    let template = toppage.generate()?;
+/// Delete the files under `output_dir` which this build did not generate, such as
+/// the page of a renamed post, and the directories left empty
+fn remove_stale_outputs(output_dir: &Path, outputs: &Outputs) -> Result<Vec<PathBuf>, ChabloError> {
+    let mut removed = vec![];
+    if output_dir.is_dir() {
+        remove_stale_in(output_dir, outputs, &mut removed)?;
+    }
+
+    Ok(removed)
+}
+
+/// Symbolic links are removed like files, never followed, so that a link to a directory
+/// outside `output_dir` cannot get its content deleted
+fn remove_stale_in(
+    dir: &Path,
+    outputs: &Outputs,
+    removed: &mut Vec<PathBuf>,
+) -> Result<(), ChabloError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            remove_stale_in(&path, outputs, removed)?;
+            if fs::read_dir(&path)?.next().is_none() {
+                fs::remove_dir(&path)?;
+            }
+        } else if !outputs.contains(&path) {
+            fs::remove_file(&path)?;
+            removed.push(path);
+        }
+    }
+
+    Ok(())
+}
 
-    let path = "public/index.html".to_string();
-    write(&template, &path)?;
+/// Copy the `static/` directory of the site verbatim into the output directory
+fn copy_static(dir: &str, output_dir: &str, outputs: &mut Outputs) -> Result<(), ChabloError> {
+    if Path::new(dir).is_dir() {
+        outputs.copy_dir(Path::new(dir), Path::new(output_dir))?;
+    }
 
     Ok(())
 }
 
+/// Map the sources of articles and pages to their output paths for `@/` links
+fn internal_links(articles: &[Article], pages_dir: &str) -> Result<InternalLinks, ChabloError> {
+    let mut links = InternalLinks::default();
+    for article in articles {
+        links.insert(&article.source, &article.path);
+    }
+    for path in collect_paths(&format!("{}/**/*.md", pages_dir))? {
+        links.insert(&path, &page_path(&path, Path::new(pages_dir)));
+    }
+
+    Ok(links)
+}
+
+/// Print the broken links, failing the build if the config asks to
+fn report_broken_links(broken: &[BrokenLink], check: LinkCheck) -> Result<(), ChabloError> {
+    if check == LinkCheck::Ignore {
+        return Ok(());
+    }
+    for link in broken {
+        eprintln!("Broken link: {link}");
+    }
+
+    match check {
+        LinkCheck::Error if !broken.is_empty() => Err(ChabloError::BrokenLinks(broken.len())),
+        _ => Ok(()),
+    }
+}
+
 // Build static htmls of an articles
-pub fn build_articles(articles: Vec<Article>) -> Result<(), ChabloError> {
+pub fn build_articles(
+    articles: Vec<Article>,
+    site: &Site,
+    theme: &Theme,
+    minify: bool,
+    outputs: &mut Outputs,
+) -> Result<(), ChabloError> {
     for article in articles {
-        let path = if article.title.contains('/') {
-            let removed_title = remove_slashes(&article.title);
-            "public/".to_string() + &removed_title.0 + ".html"
-        } else {
-            "public/".to_string() + &article.title + ".html"
+        let path = article.path.clone();
+        let template = article.generate(site, theme)?;
+
+        write_page(&template, &path, minify, outputs)?;
+    }
+
+    Ok(())
+}
+
+// Build the listing page and the feed of a section
+fn build_section(
+    section: &Section,
+    articles: &[Article],
+    site: &Site,
+    theme: &Theme,
+    minify: bool,
+    outputs: &mut Outputs,
+) -> Result<(), ChabloError> {
+    let articles: Vec<Article> = articles
+        .iter()
+        .filter(|article| article.section == section.name)
+        .cloned()
+        .collect();
+
+    if let Some(feed_path) = &section.feed {
+        let feed = Feed {
+            section: section.name.clone(),
+            articles: articles.clone(),
         };
-        let template = article.generate()?;
+        write_output(&feed.generate(site, theme)?, feed_path, outputs)?;
+    }
+
+    let toppage = TopPage {
+        section: section.name.clone(),
+        articles,
+    };
+    write_page(
+        &toppage.generate(site, theme)?,
+        &section.list_path,
+        minify,
+        outputs,
+    )?;
+
+    Ok(())
+}
+
+// Parse the standalone pages under `dir`
+fn collect_pages(
+    dir: &str,
+    markdown: &MarkdownOptions,
+    shortcodes: &Shortcodes,
+    diagrams: &Diagrams,
+) -> Result<Vec<Page>, ChabloError> {
+    let pattern = format!("{}/**/*.md", dir);
+
+    collect_paths(&pattern)?
+        .iter()
+        .map(|path| parse_page(path, Path::new(dir), markdown, shortcodes, diagrams))
+        .collect()
+}
 
-        write(&template, &path)?;
+// Build standalone pages at the same relative path under `public/`
+pub fn build_pages(
+    pages: Vec<Page>,
+    links: &InternalLinks,
+    site: &Site,
+    theme: &Theme,
+    minify: bool,
+    outputs: &mut Outputs,
+) -> Result<(), ChabloError> {
+    for mut page in pages {
+        page.body = links.resolve(&page.body);
+        page.excerpt = links.resolve(&page.excerpt);
+        let output_path = page.path.clone();
+        let template = page.generate(site, theme)?;
+
+        write_page(&template, &output_path, minify, outputs)?;
     }
 
     Ok(())
 }
 
-fn collect_articles(path: &str) -> Result<Vec<Article>, ChabloError> {
-    let mut paths: Vec<PathBuf> = collect_paths(path)?;
+// Write a page under `public/`, minified if the config asks to
+fn write_page(
+    html: &str,
+    path: &str,
+    minify: bool,
+    outputs: &mut Outputs,
+) -> Result<(), ChabloError> {
+    if minify {
+        write_output(&minify_html(html), path, outputs)
+    } else {
+        write_output(html, path, outputs)
+    }
+}
+
+// Write a file under `public/`
+fn write_output(content: &str, path: &str, outputs: &mut Outputs) -> Result<(), ChabloError> {
+    let output_path = Path::new(OUTPUT_DIR).join(path);
+
+    outputs.write(content, &output_path.to_string_lossy())
+}
+
+fn collect_section(
+    section: &Section,
+    markdown: &MarkdownOptions,
+    shortcodes: &Shortcodes,
+    diagrams: &Diagrams,
+) -> Result<Vec<Article>, ChabloError> {
+    let mut articles = collect_articles(
+        &section.dir,
+        &section.pattern,
+        markdown,
+        shortcodes,
+        diagrams,
+    )?;
+
+    for article in articles.iter_mut() {
+        article.section = section.name.clone();
+        article.path = permalink(&section.permalink, article);
+        rewrite_bundle_links(article)?;
+    }
+    sort_articles(&mut articles, section.sort);
+
+    Ok(articles)
+}
+
+// Expand the permalink pattern of a section
+fn permalink(pattern: &str, article: &Article) -> String {
+    pattern
+        .replace("{title}", &remove_slashes(&article.title).0)
+        .replace("{slug}", &article.slug)
+        .replace("{year}", &format!("{:04}", article.date.year()))
+        .replace("{month}", &format!("{:02}", article.date.month()))
+        .replace("{day}", &format!("{:02}", article.date.day()))
+}
+
+// Sort articles, keeping the order of the collected paths on ties
+fn sort_articles(articles: &mut [Article], order: SortOrder) {
+    match order {
+        SortOrder::DateDesc => articles.sort_by_key(|article| Reverse(article.date)),
+        SortOrder::DateAsc => articles.sort_by_key(|article| article.date),
+        SortOrder::Title => articles.sort_by(|a, b| a.title.cmp(&b.title)),
+    }
+}
+
+// Parse the articles matching `pattern` under `dir`
+fn collect_articles(
+    dir: &str,
+    pattern: &str,
+    markdown: &MarkdownOptions,
+    shortcodes: &Shortcodes,
+    diagrams: &Diagrams,
+) -> Result<Vec<Article>, ChabloError> {
+    let mut paths: Vec<PathBuf> = collect_paths(&format!("{dir}/{pattern}"))?;
     paths.reverse();
     let mut articles: Vec<Article> = vec![];
 
     for path in paths {
-        let article = parse(path)?;
+        let article = parse(path, Path::new(dir), markdown, shortcodes, diagrams)?;
         articles.push(article);
     }
 
     Ok(articles)
 }
 
+// Articles without an image of their own get a generated card
+fn attach_og_images(
+    articles: &mut [Article],
+    site: &Site,
+    outputs: &mut Outputs,
+) -> Result<(), ChabloError> {
+    for article in articles.iter_mut().filter(|a| a.image.is_none()) {
+        article.image = Some(generate_og_image(article, site, OUTPUT_DIR, outputs)?);
+    }
+
+    Ok(())
+}
+
 fn collect_paths(path: &str) -> Result<Vec<PathBuf>, ChabloError> {
     let mut paths: Vec<PathBuf> = vec![];
 
@@ -76,6 +441,8 @@ fn remove_slashes(title: &str) -> UpdatedTitle {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::temp_dir;
+    use chrono::NaiveDate;
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -87,17 +454,101 @@ mod tests {
 
     #[test]
     fn test_articles_ok() {
-        let path = "tests/fixtures/2050_05_30.md";
-        let articles = collect_articles(path).unwrap();
-        let result = build_articles(articles);
+        let articles = collect_articles(
+            "tests/fixtures",
+            "2050_05_30.md",
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &Diagrams::default(),
+        )
+        .unwrap();
+        let site = Site::new(&Config::default(), &articles);
+        let result = build_articles(
+            articles,
+            &site,
+            &Theme::Builtin,
+            false,
+            &mut Outputs::default(),
+        );
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_pages_ok() {
+        let site = Site::new(&Config::default(), &[]);
+        let pages = collect_pages(
+            "tests/fixtures/missing_pages",
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &Diagrams::default(),
+        )
+        .unwrap();
+        let result = build_pages(
+            pages,
+            &InternalLinks::default(),
+            &site,
+            &Theme::Builtin,
+            false,
+            &mut Outputs::default(),
+        );
 
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_collect_section_ok() {
+        let section = Section {
+            name: "fixtures".to_string(),
+            dir: "tests/fixtures".to_string(),
+            pattern: "2050_05_30.md".to_string(),
+            permalink: "fixtures/{year}/{month}/{slug}.html".to_string(),
+            ..Section::default()
+        };
+        let result = collect_section(
+            &section,
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &Diagrams::default(),
+        )
+        .unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].section, "fixtures");
+        assert_eq!(result[0].path, "fixtures/2050/05/2050_05_30.html");
+    }
+
+    #[test]
+    fn test_sort_articles() {
+        let mut articles = collect_articles(
+            "tests",
+            "**/*.md",
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &Diagrams::default(),
+        )
+        .unwrap();
+        for (i, article) in articles.iter_mut().enumerate() {
+            article.date = NaiveDate::from_ymd_opt(2000 + i as i32, 1, 1).unwrap();
+        }
+        let last_year = 2000 + articles.len() as i32 - 1;
+
+        sort_articles(&mut articles, SortOrder::DateDesc);
+        assert_eq!(articles[0].date.year(), last_year);
+
+        sort_articles(&mut articles, SortOrder::DateAsc);
+        assert_eq!(articles[0].date.year(), 2000);
+    }
+
     #[test]
     fn test_collect_article_ok() {
-        let path = "tests/fixtures/2050_05_30.md";
-        let result = collect_articles(path);
+        let result = collect_articles(
+            "tests/fixtures",
+            "2050_05_30.md",
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &Diagrams::default(),
+        );
 
         assert!(result.is_ok());
     }
@@ -107,7 +558,7 @@ mod tests {
         let path = "tests/**/*.md";
         let result = collect_paths(path).unwrap();
 
-        assert_eq!(result.len(), 2);
+        assert_eq!(result.len(), 6);
     }
 
     #[test]
@@ -134,4 +585,71 @@ mod tests {
 
         assert_eq!(result, UpdatedTitle("20230101".to_owned()));
     }
+
+    #[test]
+    fn test_remove_stale_outputs() {
+        let dir = temp_dir("stale_outputs");
+        let output_dir = dir.path();
+        fs::create_dir_all(output_dir.join("old")).unwrap();
+        fs::write(output_dir.join("kept.html"), "kept").unwrap();
+        fs::write(output_dir.join("renamed.html"), "stale").unwrap();
+        fs::write(output_dir.join("old/photo.jpg"), "stale").unwrap();
+        let mut outputs = Outputs::default();
+        outputs.mark(&output_dir.join("kept.html"));
+
+        let result = remove_stale_outputs(output_dir, &outputs).unwrap();
+        let kept = output_dir.join("kept.html").is_file();
+        let old_dir = output_dir.join("old").exists();
+
+        assert_eq!(
+            result,
+            vec![
+                output_dir.join("old/photo.jpg"),
+                output_dir.join("renamed.html")
+            ]
+        );
+        assert!(kept);
+        assert!(!old_dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remove_stale_outputs_keeps_symlink_targets() {
+        let dir = temp_dir("stale_symlinks");
+        let output_dir = dir.path().join("public");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("photo.jpg"), "outside").unwrap();
+        std::os::unix::fs::symlink(&outside, output_dir.join("linked")).unwrap();
+        let mut outputs = Outputs::default();
+        // The target of the link being an output does not keep the link
+        outputs.mark(&outside.join("photo.jpg"));
+
+        let result = remove_stale_outputs(&output_dir, &outputs).unwrap();
+        let target_kept = outside.join("photo.jpg").is_file();
+
+        assert_eq!(result, vec![output_dir.join("linked")]);
+        assert!(target_kept);
+    }
+
+    #[test]
+    fn test_clean_dir() {
+        let dir = temp_dir("clean");
+        let project_dir = dir.path().join("project");
+        let output_dir = project_dir.join("public");
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(output_dir.join("index.html"), "").unwrap();
+
+        let outside = clean_dir(&project_dir.join(".."), &project_dir);
+        let itself = clean_dir(&project_dir, &project_dir);
+        clean_dir(&output_dir, &project_dir).unwrap();
+        let removed = !output_dir.exists();
+        let missing = clean_dir(&output_dir, &project_dir);
+
+        assert!(matches!(outside, Err(ChabloError::UnsafeClean(_))));
+        assert!(matches!(itself, Err(ChabloError::UnsafeClean(_))));
+        assert!(removed);
+        assert!(missing.is_ok());
+    }
 }
@@ -0,0 +1,204 @@
+//! Page bundles: a post written as `<dir>/index.md` next to its images and attachments
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::ChabloError;
+use crate::links::{extract_values, percent_decode};
+use crate::models::{percent_encode, Article, HtmlBody};
+use crate::writer::Outputs;
+
+/// File name of the markdown source of a bundle
+pub const BUNDLE_INDEX: &str = "index.md";
+
+pub fn is_bundle(source: &Path) -> bool {
+    source.file_name().is_some_and(|name| name == BUNDLE_INDEX)
+}
+
+/// Output directory of the files of a bundle, relative to `public/`: `foo.html` keeps its
+/// files in `foo/`, `foo/index.html` in `foo/`
+pub fn bundle_output_dir(article_path: &str) -> String {
+    match article_path.strip_suffix("index.html") {
+        Some(dir) => dir.trim_end_matches('/').to_string(),
+        None => article_path
+            .strip_suffix(".html")
+            .unwrap_or(article_path)
+            .to_string(),
+    }
+}
+
+/// Files of the bundle other than markdown, relative to the bundle directory
+pub fn bundle_assets(source: &Path) -> Result<Vec<PathBuf>, ChabloError> {
+    let Some(dir) = source.parent().filter(|_| is_bundle(source)) else {
+        return Ok(vec![]);
+    };
+    let mut assets = vec![];
+    collect_files(dir, dir, &mut assets)?;
+
+    Ok(assets)
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), ChabloError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if path.extension().is_none_or(|ext| ext != "md") {
+            files.push(path.strip_prefix(root).unwrap_or(&path).to_path_buf());
+        }
+    }
+    files.sort();
+
+    Ok(())
+}
+
+/// Point the relative links of a bundled article at the copied files
+pub fn rewrite_bundle_links(article: &mut Article) -> Result<(), ChabloError> {
+    let assets = bundle_assets(&article.source)?;
+    if assets.is_empty() {
+        return Ok(());
+    }
+    let dir = bundle_output_dir(&article.path);
+    let url = |link: &str| -> Option<String> {
+        let relative = percent_decode(link.trim_start_matches("./"));
+        assets
+            .iter()
+            .any(|asset| asset.to_string_lossy().replace('\\', "/") == relative)
+            .then(|| format!("/{}", percent_encode(&format!("{dir}/{relative}"))))
+    };
+
+    article.body = rewrite_links(&article.body, url);
+    article.excerpt = rewrite_links(&article.excerpt, url);
+    if let Some(image) = article.image.as_deref().and_then(url) {
+        article.image = Some(image);
+    }
+
+    Ok(())
+}
+
+/// Replace `href` and `src` values for which `url` returns a new one
+fn rewrite_links(html: &HtmlBody, url: impl Fn(&str) -> Option<String>) -> HtmlBody {
+    let mut output = html.0.clone();
+    for attribute in [" href=\"", " src=\""] {
+        for link in extract_values(&html.0, &[attribute]) {
+            if let Some(new_url) = url(&link) {
+                output = output.replace(
+                    &format!("{attribute}{link}\""),
+                    &format!("{attribute}{new_url}\""),
+                );
+            }
+        }
+    }
+
+    HtmlBody(output)
+}
+
+/// Copy the files of a bundled article into `output_dir`
+pub fn copy_bundle(
+    article: &Article,
+    output_dir: &Path,
+    outputs: &mut Outputs,
+) -> Result<(), ChabloError> {
+    let Some(source_dir) = article.source.parent() else {
+        return Ok(());
+    };
+    let dest_dir = output_dir.join(bundle_output_dir(&article.path));
+
+    for asset in bundle_assets(&article.source)? {
+        let dest = dest_dir.join(&asset);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        outputs.copy_file(&source_dir.join(&asset), &dest)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, temp_dir};
+    use pretty_assertions::assert_eq;
+
+    /// Bundle of an article written under `root`
+    fn bundle(root: &Path) -> PathBuf {
+        let dir = root.join("2023/04/07");
+        fs::create_dir_all(dir.join("files")).unwrap();
+        fs::write(dir.join("index.md"), "---\ntitle: 花見\n---\n").unwrap();
+        fs::write(dir.join("写真.jpg"), "jpg").unwrap();
+        fs::write(dir.join("files/memo.pdf"), "pdf").unwrap();
+
+        dir
+    }
+
+    fn article(source: PathBuf) -> Article {
+        Article {
+            body: HtmlBody(
+                r#"<img src="%E5%86%99%E7%9C%9F.jpg"><a href="./files/memo.pdf">memo</a><a href="other.pdf">x</a>"#
+                    .to_string(),
+            ),
+            image: Some("%E5%86%99%E7%9C%9F.jpg".to_string()),
+            slug: "07".to_string(),
+            date: chrono::NaiveDate::from_ymd_opt(2023, 4, 7).unwrap(),
+            source,
+            ..test_utils::article("花見")
+        }
+    }
+
+    #[test]
+    fn test_bundle_output_dir() {
+        assert_eq!(bundle_output_dir("花見.html"), "花見");
+        assert_eq!(bundle_output_dir("2023/04/07/index.html"), "2023/04/07");
+    }
+
+    #[test]
+    fn test_bundle_assets() {
+        let root = temp_dir("bundle_assets");
+        let dir = bundle(root.path());
+
+        let result = bundle_assets(&dir.join("index.md")).unwrap();
+        let not_bundle = bundle_assets(&dir.join("other.md")).unwrap();
+
+        assert_eq!(
+            result,
+            vec![PathBuf::from("files/memo.pdf"), PathBuf::from("写真.jpg")]
+        );
+        assert!(not_bundle.is_empty());
+    }
+
+    #[test]
+    fn test_rewrite_bundle_links() {
+        let root = temp_dir("bundle_rewrite");
+        let mut article = article(bundle(root.path()).join("index.md"));
+
+        rewrite_bundle_links(&mut article).unwrap();
+
+        let photo = "/%E8%8A%B1%E8%A6%8B/%E5%86%99%E7%9C%9F.jpg";
+        assert_eq!(
+            article.body.0,
+            format!(
+                r#"<img src="{photo}"><a href="/%E8%8A%B1%E8%A6%8B/files/memo.pdf">memo</a><a href="other.pdf">x</a>"#
+            )
+        );
+        assert_eq!(article.image.as_deref(), Some(photo));
+    }
+
+    #[test]
+    fn test_copy_bundle() {
+        let root = temp_dir("bundle_copy");
+        let dir = bundle(root.path());
+        let output_dir = root.path().join("public");
+
+        copy_bundle(
+            &article(dir.join("index.md")),
+            &output_dir,
+            &mut Outputs::default(),
+        )
+        .unwrap();
+        let photo = fs::read_to_string(output_dir.join("花見/写真.jpg")).unwrap();
+        let memo = fs::read_to_string(output_dir.join("花見/files/memo.pdf")).unwrap();
+
+        assert_eq!(photo, "jpg");
+        assert_eq!(memo, "pdf");
+    }
+}
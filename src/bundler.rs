@@ -0,0 +1,358 @@
+//! Compile, concatenate and minify stylesheets and scripts
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::builder::BuildMode;
+use crate::config::Bundle;
+use crate::errors::ChabloError;
+use crate::writer::Outputs;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BundleKind {
+    Css,
+    Js,
+}
+
+/// Source file of a bundle, after compilation
+struct Chunk {
+    path: PathBuf,
+    source: String,
+    code: String,
+    /// Whether the lines of `code` are the lines of `source`, which is not the case of SCSS
+    line_for_line: bool,
+}
+
+/// Source map revision 3, mapping the lines of a development bundle to its sources
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SourceMap {
+    version: u8,
+    file: String,
+    sources: Vec<String>,
+    sources_content: Vec<String>,
+    names: Vec<String>,
+    mappings: String,
+}
+
+/// Write `bundle.output` under `output_dir`. Production bundles are minified, development
+/// ones are kept readable and get a source map next to them.
+pub fn build_bundle(
+    bundle: &Bundle,
+    output_dir: &Path,
+    mode: BuildMode,
+    outputs: &mut Outputs,
+) -> Result<(), ChabloError> {
+    let kind = match Path::new(&bundle.output)
+        .extension()
+        .and_then(|e| e.to_str())
+    {
+        Some("css") => BundleKind::Css,
+        Some("js") => BundleKind::Js,
+        _ => {
+            return Err(bundle_error(
+                bundle,
+                "the output must be a .css or .js file",
+            ))
+        }
+    };
+    let chunks = bundle
+        .inputs
+        .iter()
+        .map(|input| compile(input, kind).map_err(|e| bundle_error(bundle, &e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let output_path = output_dir.join(&bundle.output);
+
+    match mode {
+        BuildMode::Production => {
+            let code = minify(&concat(&chunks, kind), kind).map_err(|e| bundle_error(bundle, e))?;
+            outputs.write(code, &output_path.to_string_lossy())
+        }
+        BuildMode::Development => {
+            let file_name = output_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let map_comment = match kind {
+                BundleKind::Css => format!("/*# sourceMappingURL={file_name}.map */\n"),
+                BundleKind::Js => format!("//# sourceMappingURL={file_name}.map\n"),
+            };
+            let map = source_map(&chunks, kind, file_name);
+            outputs.write(
+                concat(&chunks, kind) + &map_comment,
+                &output_path.to_string_lossy(),
+            )?;
+            outputs.write(
+                serde_json::to_string(&map)?,
+                &format!("{}.map", output_path.to_string_lossy()),
+            )
+        }
+    }
+}
+
+fn bundle_error(bundle: &Bundle, message: &str) -> ChabloError {
+    ChabloError::BundleError(format!("{}: {}", bundle.output, message.trim_end()))
+}
+
+fn compile(input: &Path, kind: BundleKind) -> Result<Chunk, String> {
+    let extension = input.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let source = fs::read_to_string(input).map_err(|e| format!("{}: {}", input.display(), e))?;
+    let (code, line_for_line) = match (kind, extension) {
+        (BundleKind::Css, "css") | (BundleKind::Js, "js") => (source.clone(), true),
+        (BundleKind::Css, "scss" | "sass") => {
+            let code =
+                grass::from_path(input, &grass::Options::default()).map_err(|e| e.to_string())?;
+            (code, false)
+        }
+        _ => return Err(format!("{}: unsupported input", input.display())),
+    };
+
+    Ok(Chunk {
+        path: input.to_path_buf(),
+        source,
+        code,
+        line_for_line,
+    })
+}
+
+/// Join the chunks, ending each one with a newline. Scripts are also separated by a
+/// semicolon, so that a file relying on automatic semicolon insertion at its end does
+/// not run into the next one.
+fn concat(chunks: &[Chunk], kind: BundleKind) -> String {
+    let mut bundle = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        if kind == BundleKind::Js && i > 0 {
+            bundle.push_str(";\n");
+        }
+        bundle.push_str(&chunk.code);
+        if !chunk.code.ends_with('\n') {
+            bundle.push('\n');
+        }
+    }
+
+    bundle
+}
+
+fn minify(code: &str, kind: BundleKind) -> Result<String, &'static str> {
+    match kind {
+        BundleKind::Css => minifier::css::minify(code).map(|minified| minified.to_string()),
+        BundleKind::Js => minifier::js::minify(code).map(|minified| minified.to_string()),
+    }
+}
+
+/// Map every line of the bundle made by `concat` to the line of its source. Lines
+/// compiled from SCSS map to the start of their file, as the compiler does not keep
+/// their positions.
+fn source_map(chunks: &[Chunk], kind: BundleKind, file: String) -> SourceMap {
+    let mut lines: Vec<String> = vec![];
+    // Fields of the previous segment, which the next one is relative to
+    let (mut previous_source, mut previous_line) = (0, 0);
+    for (i, chunk) in chunks.iter().enumerate() {
+        if kind == BundleKind::Js && i > 0 {
+            lines.push(String::new());
+        }
+        for line in 0..chunk.code.lines().count() {
+            let source_line = if chunk.line_for_line { line as i64 } else { 0 };
+            let segment = [
+                0,
+                i as i64 - previous_source,
+                source_line - previous_line,
+                0,
+            ];
+            lines.push(segment.into_iter().map(vlq).collect());
+            (previous_source, previous_line) = (i as i64, source_line);
+        }
+    }
+
+    SourceMap {
+        version: 3,
+        file,
+        sources: chunks
+            .iter()
+            .map(|chunk| chunk.path.to_string_lossy().replace('\\', "/"))
+            .collect(),
+        sources_content: chunks.iter().map(|chunk| chunk.source.clone()).collect(),
+        names: vec![],
+        mappings: lines.join(";"),
+    }
+}
+
+/// Base64 VLQ encoding of a source map field
+fn vlq(value: i64) -> String {
+    let mut value = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+    let mut encoded = String::new();
+    loop {
+        let mut digit = value & 0b11111;
+        value >>= 5;
+        if value > 0 {
+            digit |= 0b100000;
+        }
+        encoded.push(BASE64[digit as usize] as char);
+        if value == 0 {
+            return encoded;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    fn sources(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir = temp_dir(name);
+        for (file, content) in files {
+            fs::write(dir.path().join(file), content).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn test_vlq() {
+        assert_eq!(vlq(0), "A");
+        assert_eq!(vlq(1), "C");
+        assert_eq!(vlq(-1), "D");
+        assert_eq!(vlq(16), "gB");
+        assert_eq!(vlq(-17), "jB");
+    }
+
+    #[test]
+    fn test_build_css_bundle() {
+        let dir = sources(
+            "bundle_css",
+            &[
+                ("reset.css", "body {\n  margin: 0;\n}\n"),
+                (
+                    "main.scss",
+                    "$color: red;\na {\n  b { color: $color; }\n}\n",
+                ),
+            ],
+        );
+        let bundle = Bundle {
+            output: "css/site.css".to_string(),
+            inputs: vec![dir.path().join("reset.css"), dir.path().join("main.scss")],
+        };
+
+        build_bundle(
+            &bundle,
+            dir.path(),
+            BuildMode::Production,
+            &mut Outputs::default(),
+        )
+        .unwrap();
+        let result = fs::read_to_string(dir.path().join("css/site.css")).unwrap();
+
+        assert_eq!(result, "body{margin:0;}a b{color:red;}");
+    }
+
+    #[test]
+    fn test_build_js_bundle() {
+        let dir = sources(
+            "bundle_js",
+            &[
+                ("a.js", "var a = 1\n"),
+                ("b.js", "// b\nconsole.log( a );\n"),
+            ],
+        );
+        let bundle = Bundle {
+            output: "app.js".to_string(),
+            inputs: vec![dir.path().join("a.js"), dir.path().join("b.js")],
+        };
+
+        build_bundle(
+            &bundle,
+            dir.path(),
+            BuildMode::Production,
+            &mut Outputs::default(),
+        )
+        .unwrap();
+        let result = fs::read_to_string(dir.path().join("app.js")).unwrap();
+
+        assert_eq!(result, "var a=1;console.log(a);");
+    }
+
+    #[test]
+    fn test_build_development_bundle_with_source_map() {
+        let dir = sources(
+            "bundle_source_map",
+            &[("a.css", "a {}\n"), ("b.css", "b {\n  color: red;\n}\n")],
+        );
+        let bundle = Bundle {
+            output: "site.css".to_string(),
+            inputs: vec![dir.path().join("a.css"), dir.path().join("b.css")],
+        };
+
+        build_bundle(
+            &bundle,
+            dir.path(),
+            BuildMode::Development,
+            &mut Outputs::default(),
+        )
+        .unwrap();
+        let result = fs::read_to_string(dir.path().join("site.css")).unwrap();
+        let map: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("site.css.map")).unwrap())
+                .unwrap();
+
+        assert_eq!(
+            result,
+            "a {}\nb {\n  color: red;\n}\n/*# sourceMappingURL=site.css.map */\n"
+        );
+        assert_eq!(map["file"], "site.css");
+        assert_eq!(map["sourcesContent"][1], "b {\n  color: red;\n}\n");
+        // a.css line 1, then b.css lines 1 to 3
+        assert_eq!(map["mappings"], "AAAA;ACAA;AACA;AACA");
+    }
+
+    #[test]
+    fn test_build_bundle_with_scss_error() {
+        let dir = sources(
+            "bundle_scss_error",
+            &[("main.scss", "a {\n  color: $missing;\n}\n")],
+        );
+        let bundle = Bundle {
+            output: "main.css".to_string(),
+            inputs: vec![dir.path().join("main.scss")],
+        };
+
+        let result = build_bundle(
+            &bundle,
+            dir.path(),
+            BuildMode::Production,
+            &mut Outputs::default(),
+        );
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("main.css: Error: Undefined variable."));
+        assert!(message.contains("main.scss:2:"));
+    }
+
+    #[test]
+    fn test_build_bundle_with_unsupported_input() {
+        let dir = sources("bundle_unsupported", &[("app.js", "")]);
+        let bundle = Bundle {
+            output: "main.css".to_string(),
+            inputs: vec![dir.path().join("app.js")],
+        };
+
+        let result = build_bundle(
+            &bundle,
+            dir.path(),
+            BuildMode::Production,
+            &mut Outputs::default(),
+        );
+
+        assert!(matches!(result, Err(ChabloError::BundleError(_))));
+    }
+}
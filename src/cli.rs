@@ -20,8 +20,27 @@ pub enum Commands {
     /// Build the blog
     Build,
 
-    /// Run the server
+    /// Build the blog for development and run the server
     Serve,
+
+    /// Remove the generated site
+    Clean,
+
+    /// Check the outbound links of the built site
+    CheckLinks {
+        /// Number of requests in flight at once
+        #[clap(long, default_value_t = 8)]
+        concurrency: usize,
+        /// Timeout of each request in seconds
+        #[clap(long, default_value_t = 10)]
+        timeout: u64,
+        /// Days a cached result stays valid, up to 100 years
+        #[clap(long, default_value_t = 7, value_parser = clap::value_parser!(i64).range(0..=36500))]
+        cache_days: i64,
+        /// Check every link again, ignoring and not saving the cache
+        #[clap(long)]
+        no_cache: bool,
+    },
 }
 
 impl ChabloOpt {
@@ -0,0 +1,221 @@
+//! Gzip and Brotli copies of text outputs, served in place of the originals to clients
+//! accepting them
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::CompressionOptions;
+use crate::errors::ChabloError;
+use crate::writer::Outputs;
+
+/// Extensions of the outputs worth compressing
+pub const COMPRESSIBLE_EXTENSIONS: [&str; 5] = ["html", "css", "js", "xml", "json"];
+
+const BROTLI_QUALITY: u32 = 11;
+const BROTLI_WINDOW: u32 = 22;
+
+/// Content encodings in the order of preference, with the extension of their copies
+pub const ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Write `.gz` and `.br` siblings of the compressible `outputs` under `output_dir`.
+/// Copies of an earlier build are kept when they still decompress to their original.
+pub fn precompress(
+    output_dir: &Path,
+    outputs: &mut Outputs,
+    options: &CompressionOptions,
+) -> Result<(), ChabloError> {
+    if !options.enabled || !output_dir.is_dir() {
+        return Ok(());
+    }
+    let output_dir = fs::canonicalize(output_dir)?;
+
+    let paths: Vec<PathBuf> = outputs.paths().cloned().collect();
+    for path in &paths {
+        if !path.starts_with(&output_dir) || !is_compressible(path) {
+            continue;
+        }
+        if fs::metadata(path)?.len() < options.min_size {
+            continue;
+        }
+        let content = fs::read(path)?;
+        for (encoding, extension) in ENCODINGS {
+            let compressed = sibling(path, extension);
+            if is_compressed_copy(&compressed, &content, encoding) {
+                outputs.mark(&compressed);
+                continue;
+            }
+            let data = compress(&content, encoding)?;
+            outputs.write(data, &compressed.to_string_lossy())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the file has one of `COMPRESSIBLE_EXTENSIONS`
+pub fn is_compressible(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|extension| COMPRESSIBLE_EXTENSIONS.contains(&extension))
+}
+
+/// Path of the copy with the extension added, e.g. `index.html.gz`
+pub fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(".");
+    sibling.push(extension);
+
+    PathBuf::from(sibling)
+}
+
+fn compress(content: &[u8], encoding: &str) -> Result<Vec<u8>, ChabloError> {
+    match encoding {
+        "br" => {
+            let mut encoder =
+                brotli::CompressorWriter::new(vec![], 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+            encoder.write_all(content)?;
+            Ok(encoder.into_inner())
+        }
+        _ => {
+            let mut encoder = GzEncoder::new(vec![], Compression::best());
+            encoder.write_all(content)?;
+            Ok(encoder.finish()?)
+        }
+    }
+}
+
+fn decompress(data: &[u8], encoding: &str) -> io::Result<Vec<u8>> {
+    let mut content = vec![];
+    match encoding {
+        "br" => brotli::Decompressor::new(data, 4096).read_to_end(&mut content)?,
+        _ => GzDecoder::new(data).read_to_end(&mut content)?,
+    };
+
+    Ok(content)
+}
+
+/// Whether `path` exists and decompresses to `content`, whatever its modification time
+fn is_compressed_copy(path: &Path, content: &[u8], encoding: &str) -> bool {
+    fs::read(path)
+        .and_then(|data| decompress(&data, encoding))
+        .is_ok_and(|decompressed| decompressed == content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+    use pretty_assertions::assert_eq;
+
+    fn options() -> CompressionOptions {
+        CompressionOptions {
+            enabled: true,
+            min_size: 100,
+        }
+    }
+
+    #[test]
+    fn test_precompress() {
+        let dir = temp_dir("precompress");
+        let dir = dir.path();
+        let page = "<p>圧縮</p>\n".repeat(20);
+        let files = [
+            ("index.html", page.as_str()),
+            ("small.css", "a{}"),
+            ("photo.png", page.as_str()),
+        ];
+        for (file, content) in files {
+            fs::write(dir.join(file), content).unwrap();
+        }
+        let mut outputs = Outputs::default();
+        for (file, _) in files {
+            outputs.mark(&dir.join(file));
+        }
+
+        precompress(dir, &mut outputs, &options()).unwrap();
+        let mut gzip = String::new();
+        GzDecoder::new(fs::File::open(dir.join("index.html.gz")).unwrap())
+            .read_to_string(&mut gzip)
+            .unwrap();
+        let mut brotli = String::new();
+        brotli::Decompressor::new(fs::File::open(dir.join("index.html.br")).unwrap(), 4096)
+            .read_to_string(&mut brotli)
+            .unwrap();
+        let mut result: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        result.sort();
+
+        assert_eq!(gzip, page);
+        assert_eq!(brotli, page);
+        assert_eq!(
+            result,
+            vec![
+                "index.html",
+                "index.html.br",
+                "index.html.gz",
+                "photo.png",
+                "small.css"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_precompress_replaces_stale_copies() {
+        let dir = temp_dir("precompress_stale");
+        let dir = dir.path();
+        let page = dir.join("index.html");
+        let mut outputs = Outputs::default();
+        outputs
+            .write("a".repeat(200), &page.to_string_lossy())
+            .unwrap();
+        precompress(dir, &mut outputs, &options()).unwrap();
+        // Written after the copies, yet older than them
+        let copied = fs::metadata(sibling(&page, "gz"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        fs::write(&page, "b".repeat(200)).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&page)
+            .unwrap()
+            .set_modified(copied - std::time::Duration::from_secs(60))
+            .unwrap();
+
+        precompress(dir, &mut outputs, &options()).unwrap();
+        let gzip = decompress(&fs::read(sibling(&page, "gz")).unwrap(), "gzip").unwrap();
+
+        assert_eq!(gzip, "b".repeat(200).into_bytes());
+    }
+
+    #[test]
+    fn test_precompress_disabled() {
+        let dir = temp_dir("precompress_disabled");
+        let dir = dir.path();
+        fs::write(dir.join("index.html"), "x".repeat(200)).unwrap();
+        let mut outputs = Outputs::default();
+        outputs.mark(&dir.join("index.html"));
+        let options = CompressionOptions {
+            enabled: false,
+            ..options()
+        };
+
+        precompress(dir, &mut outputs, &options).unwrap();
+        let result = dir.join("index.html.gz").exists();
+
+        assert!(!result);
+    }
+
+    #[test]
+    fn test_sibling() {
+        let result = sibling(Path::new("public/css/main.css"), "gz");
+
+        assert_eq!(result, PathBuf::from("public/css/main.css.gz"));
+    }
+}
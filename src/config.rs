@@ -0,0 +1,407 @@
+//! Site configuration
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use pulldown_cmark::Options;
+use serde::Deserialize;
+
+use crate::errors::ChabloError;
+use crate::models::{FrontMatter, Menu, Section, AUTHOR, BASE_URL, BLOG_TITLE, DESCRIPTION};
+
+pub const CONFIG_PATH: &str = "chablo.toml";
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct Config {
+    pub title: String,
+    pub description: String,
+    /// URL the site is served from, without a trailing slash
+    pub base_url: String,
+    pub author: String,
+    /// Navigation menus shown on every page
+    pub menus: Vec<Menu>,
+    /// Content sections, the diary alone when unset
+    pub sections: Vec<Section>,
+    /// Markdown extensions, overridable per article with `markdown.<name>` front matter
+    pub markdown: MarkdownOptions,
+    /// Responsive variants of the images in posts
+    pub images: ImageOptions,
+    /// Stylesheets and scripts compiled into the output
+    pub bundles: Vec<Bundle>,
+    /// Precompressed copies of the text outputs
+    pub compression: CompressionOptions,
+    /// Strip the indentation and comments of the generated pages
+    pub minify_html: bool,
+    /// What to do about links to missing pages, anchors or assets
+    pub broken_links: LinkCheck,
+    /// Theme under `themes/<name>/`, the built-in templates are used when unset
+    pub theme: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            title: BLOG_TITLE.to_string(),
+            description: DESCRIPTION.to_string(),
+            base_url: BASE_URL.to_string(),
+            author: AUTHOR.to_string(),
+            menus: vec![],
+            sections: vec![Section::default()],
+            markdown: MarkdownOptions::default(),
+            images: ImageOptions::default(),
+            bundles: vec![],
+            compression: CompressionOptions::default(),
+            minify_html: false,
+            broken_links: LinkCheck::default(),
+            theme: None,
+        }
+    }
+}
+
+/// Resizing of the local JPEG and PNG images referenced by posts
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ImageOptions {
+    pub enabled: bool,
+    /// Widths of the variants in pixels, the largest also bounding the main image
+    pub widths: Vec<u32>,
+    /// JPEG quality from 1 to 100
+    pub quality: u8,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            enabled: true,
+            widths: vec![480, 960, 1600],
+            quality: 80,
+        }
+    }
+}
+
+/// Stylesheets or scripts concatenated into one output file
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct Bundle {
+    /// Path under `public/` ending with `.css` or `.js`
+    pub output: String,
+    /// Files in order, SCSS and Sass ones being compiled into CSS
+    pub inputs: Vec<PathBuf>,
+}
+
+/// Gzip and Brotli copies of the HTML, CSS, JS, XML and JSON outputs
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct CompressionOptions {
+    pub enabled: bool,
+    /// Size in bytes under which a file is not worth compressing
+    pub min_size: u64,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        CompressionOptions {
+            enabled: true,
+            min_size: 1024,
+        }
+    }
+}
+
+/// Handling of the broken links found after a build
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkCheck {
+    /// Fail the build
+    Error,
+    /// Print the broken links
+    #[default]
+    Warn,
+    Ignore,
+}
+
+/// Markdown extensions enabled on top of CommonMark
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct MarkdownOptions {
+    pub strikethrough: bool,
+    pub tables: bool,
+    pub footnotes: bool,
+    pub tasklists: bool,
+    pub smart_punctuation: bool,
+    pub heading_attributes: bool,
+    pub definition_lists: bool,
+    /// GitHub flavored alerts such as `> [!NOTE]`
+    pub gfm: bool,
+    /// `$...$` and `$$...$$` rendered into MathML, off by default as invalid TeX fails
+    /// the build and `$` is common in prose
+    pub math: bool,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        MarkdownOptions {
+            strikethrough: true,
+            tables: true,
+            footnotes: true,
+            tasklists: true,
+            smart_punctuation: false,
+            heading_attributes: true,
+            definition_lists: true,
+            gfm: true,
+            math: false,
+        }
+    }
+}
+
+impl MarkdownOptions {
+    /// Apply `markdown.<name>: true|false` fields of an article
+    pub fn with_front_matter(mut self, front_matter: &FrontMatter) -> Self {
+        let fields = [
+            ("strikethrough", &mut self.strikethrough),
+            ("tables", &mut self.tables),
+            ("footnotes", &mut self.footnotes),
+            ("tasklists", &mut self.tasklists),
+            ("smart_punctuation", &mut self.smart_punctuation),
+            ("heading_attributes", &mut self.heading_attributes),
+            ("definition_lists", &mut self.definition_lists),
+            ("gfm", &mut self.gfm),
+            ("math", &mut self.math),
+        ];
+        for (name, enabled) in fields {
+            let value = front_matter.get(&format!("markdown.{name}"));
+            if let Some(value) = value.and_then(|value| value.parse().ok()) {
+                *enabled = value;
+            }
+        }
+
+        self
+    }
+
+    pub fn to_options(self) -> Options {
+        let flags = [
+            (self.strikethrough, Options::ENABLE_STRIKETHROUGH),
+            (self.tables, Options::ENABLE_TABLES),
+            (self.footnotes, Options::ENABLE_FOOTNOTES),
+            (self.tasklists, Options::ENABLE_TASKLISTS),
+            (self.smart_punctuation, Options::ENABLE_SMART_PUNCTUATION),
+            (self.heading_attributes, Options::ENABLE_HEADING_ATTRIBUTES),
+            (self.definition_lists, Options::ENABLE_DEFINITION_LIST),
+            (self.gfm, Options::ENABLE_GFM),
+            (self.math, Options::ENABLE_MATH),
+        ];
+
+        flags
+            .into_iter()
+            .filter(|(enabled, _)| *enabled)
+            .fold(Options::empty(), |options, (_, flag)| options | flag)
+    }
+}
+
+impl Config {
+    /// Load `chablo.toml`, falling back to the defaults when it does not exist
+    pub fn load() -> Result<Self, ChabloError> {
+        Self::from_path(CONFIG_PATH)
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, ChabloError> {
+        if !path.as_ref().exists() {
+            return Ok(Config::default());
+        }
+        let content = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&content)?;
+        config.check_sections()?;
+
+        Ok(config)
+    }
+
+    /// Fail when two sections write their listing or feed to the same path
+    fn check_sections(&self) -> Result<(), ChabloError> {
+        let mut outputs: BTreeMap<&str, &str> = BTreeMap::new();
+        for section in &self.sections {
+            let paths = [Some(&section.list_path), section.feed.as_ref()];
+            for path in paths.into_iter().flatten() {
+                if let Some(other) = outputs.insert(path, &section.name) {
+                    return Err(ChabloError::DuplicateOutput(format!(
+                        "{path} of the sections {other} and {}",
+                        section.name
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SortOrder;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_load_config_ok() {
+        let result = Config::load();
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_config_with_missing_file() {
+        let result = Config::from_path("tests/fixtures/missing.toml").unwrap();
+
+        assert_eq!(result, Config::default());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let content = "title = \"日記\"\nbase_url = \"http://localhost:8080\"\ntheme = \"simple\"";
+        let result: Config = toml::from_str(content).unwrap();
+
+        assert_eq!(result.title, "日記");
+        assert_eq!(result.base_url, "http://localhost:8080");
+        assert_eq!(result.description, DESCRIPTION);
+        assert_eq!(result.theme, Some("simple".to_string()));
+    }
+
+    #[test]
+    fn test_parse_config_with_menus() {
+        let content = r#"
+[[menus]]
+name = "About"
+url = "/about.html"
+weight = 10
+
+[[menus.children]]
+name = "GitHub"
+url = "https://github.com/chansuke"
+"#;
+        let result: Config = toml::from_str(content).unwrap();
+
+        assert_eq!(result.menus.len(), 1);
+        assert_eq!(result.menus[0].weight, 10);
+        assert_eq!(result.menus[0].children[0].name, "GitHub");
+    }
+
+    #[test]
+    fn test_parse_config_with_sections() {
+        let content = r#"
+[[sections]]
+name = "notes"
+dir = "notes"
+sort = "title"
+list_path = "notes/index.html"
+permalink = "notes/{slug}.html"
+"#;
+        let result: Config = toml::from_str(content).unwrap();
+
+        assert_eq!(result.sections.len(), 1);
+        assert_eq!(result.sections[0].glob(), "notes/**/*.md");
+        assert_eq!(result.sections[0].sort, SortOrder::Title);
+        assert_eq!(result.sections[0].template, "article.html");
+        assert_eq!(result.sections[0].feed, Some("notes/feed.xml".to_string()));
+    }
+
+    #[test]
+    fn test_parse_config_with_section_defaults() {
+        let content = "[[sections]]\nname = \"notes\"";
+        let result: Config = toml::from_str(content).unwrap();
+
+        assert_eq!(result.sections[0].dir, "notes");
+        assert_eq!(result.sections[0].list_path, "notes/index.html");
+        assert_eq!(result.sections[0].permalink, "notes/{title}.html");
+        assert_eq!(result.sections[0].feed, Some("notes/feed.xml".to_string()));
+        assert!(result.check_sections().is_ok());
+    }
+
+    #[test]
+    fn test_parse_config_with_section_feed() {
+        let content = r#"
+[[sections]]
+name = "notes"
+feed = false
+
+[[sections]]
+name = "diary"
+feed = "atom.xml"
+
+[[sections]]
+name = "links"
+feed = true
+"#;
+        let result: Config = toml::from_str(content).unwrap();
+
+        assert_eq!(result.sections[0].feed, None);
+        assert_eq!(result.sections[1].feed, Some("atom.xml".to_string()));
+        assert_eq!(result.sections[2].feed, Some("links/feed.xml".to_string()));
+    }
+
+    #[test]
+    fn test_check_sections_with_same_output() {
+        let content = r#"
+[[sections]]
+name = "diary"
+list_path = "index.html"
+
+[[sections]]
+name = "notes"
+list_path = "index.html"
+"#;
+        let config: Config = toml::from_str(content).unwrap();
+
+        let result = config.check_sections();
+
+        assert!(matches!(result, Err(ChabloError::DuplicateOutput(_))));
+    }
+
+    #[test]
+    fn test_parse_config_with_bundles() {
+        let content = r#"
+[[bundles]]
+output = "css/site.css"
+inputs = ["assets/reset.css", "assets/main.scss"]
+"#;
+        let result: Config = toml::from_str(content).unwrap();
+
+        assert_eq!(
+            result.bundles,
+            vec![Bundle {
+                output: "css/site.css".to_string(),
+                inputs: vec![
+                    PathBuf::from("assets/reset.css"),
+                    PathBuf::from("assets/main.scss")
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_markdown_options_with_front_matter() {
+        let mut front_matter = FrontMatter::default();
+        front_matter
+            .0
+            .insert("markdown.tables".to_string(), "false".to_string());
+        front_matter
+            .0
+            .insert("markdown.smart_punctuation".to_string(), "true".to_string());
+
+        let result = MarkdownOptions::default()
+            .with_front_matter(&front_matter)
+            .to_options();
+
+        assert!(!result.contains(Options::ENABLE_TABLES));
+        assert!(result.contains(Options::ENABLE_SMART_PUNCTUATION));
+        assert!(result.contains(Options::ENABLE_STRIKETHROUGH));
+    }
+
+    #[test]
+    fn test_parse_config_with_markdown() {
+        let content = "broken_links = \"error\"\nminify_html = true\n[markdown]\nfootnotes = false";
+        let result: Config = toml::from_str(content).unwrap();
+
+        assert_eq!(result.broken_links, LinkCheck::Error);
+        assert!(result.minify_html);
+        assert!(!result.markdown.footnotes);
+        assert!(result.markdown.tables);
+    }
+}
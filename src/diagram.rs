@@ -0,0 +1,218 @@
+//! Render `dot` and `mermaid` code blocks into inline SVG
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use layout::backends::svg::SVGWriter;
+use layout::gv::{DotParser, GraphBuilder};
+use log::warn;
+
+use crate::og_image::content_hash;
+use crate::writer::write;
+
+/// Directory keeping rendered diagrams between builds
+pub const DIAGRAM_CACHE_DIR: &str = ".cache/diagrams";
+
+/// Languages of the code blocks rendered as diagrams
+const DIAGRAM_LANGS: [&str; 2] = ["dot", "mermaid"];
+
+pub fn is_diagram(lang: &str) -> bool {
+    DIAGRAM_LANGS.contains(&lang)
+}
+
+/// Renderer of the diagrams of a build, caching their SVG under `cache_dir`
+#[derive(Debug)]
+pub struct Diagrams {
+    cache_dir: PathBuf,
+    /// mermaid-cli, `mmdc` unless replaced in tests
+    mermaid_command: &'static str,
+    /// Set once `mmdc` could not be started, so that it is not tried for every block
+    mermaid_unavailable: AtomicBool,
+}
+
+impl Default for Diagrams {
+    fn default() -> Self {
+        Self::new(PathBuf::from(DIAGRAM_CACHE_DIR))
+    }
+}
+
+impl Diagrams {
+    pub fn new(cache_dir: PathBuf) -> Self {
+        Diagrams {
+            cache_dir,
+            mermaid_command: "mmdc",
+            mermaid_unavailable: AtomicBool::new(false),
+        }
+    }
+
+    /// Render a diagram into SVG markup, reusing the cached copy. `None` means the
+    /// renderer is unavailable or failed, and the block is kept as code.
+    pub fn render(&self, lang: &str, source: &str) -> Option<String> {
+        let cache_path = self
+            .cache_dir
+            .join(format!("{}.svg", content_hash(&[lang, source])));
+        if let Ok(svg) = fs::read_to_string(&cache_path) {
+            return Some(svg);
+        }
+
+        let svg = match lang {
+            "dot" => render_dot(source),
+            "mermaid" => self.render_mermaid(source),
+            _ => None,
+        }?;
+        let svg = strip_xml_declaration(&svg).to_string();
+
+        if let Err(e) = fs::create_dir_all(&self.cache_dir)
+            .map_err(Into::into)
+            .and_then(|_| write(&svg, &cache_path.to_string_lossy()))
+        {
+            warn!("Failed to cache diagram {}: {e}", cache_path.display());
+        }
+
+        Some(svg)
+    }
+
+    /// Render mermaid source with a locally installed `mmdc` (mermaid-cli)
+    fn render_mermaid(&self, source: &str) -> Option<String> {
+        if self.mermaid_unavailable.load(Ordering::Relaxed) {
+            return None;
+        }
+        let svg = render_mermaid_with(self.mermaid_command, source, &self.cache_dir);
+        if let Err(e) = &svg {
+            warn!("mmdc is unavailable, keeping the mermaid code blocks: {e}");
+            self.mermaid_unavailable.store(true, Ordering::Relaxed);
+        }
+
+        svg.ok().flatten()
+    }
+}
+
+/// Lay out Graphviz source with the pure Rust renderer
+fn render_dot(source: &str) -> Option<String> {
+    let mut parser = DotParser::new(source);
+    let graph = match parser.process() {
+        Ok(graph) => graph,
+        Err(e) => {
+            warn!("Failed to parse dot diagram: {e}");
+            return None;
+        }
+    };
+
+    let mut builder = GraphBuilder::new();
+    builder.visit_graph(&graph);
+    let mut visual_graph = builder.get();
+    let mut svg = SVGWriter::new();
+    visual_graph.do_it(false, false, false, &mut svg);
+
+    Some(svg.finalize())
+}
+
+/// Run the mermaid-cli `command` in `work_dir`. The error is that the command could not
+/// be found, `Ok(None)` that it failed to render this diagram.
+fn render_mermaid_with(
+    command: &str,
+    source: &str,
+    work_dir: &Path,
+) -> Result<Option<String>, std::io::Error> {
+    let hash = content_hash(&[source]);
+    let input = work_dir.join(format!("{hash}.mmd"));
+    let output = work_dir.join(format!("{hash}.tmp.svg"));
+    if fs::create_dir_all(work_dir)
+        .and_then(|_| fs::write(&input, source))
+        .is_err()
+    {
+        return Ok(None);
+    }
+
+    let status = Command::new(command)
+        .arg("--input")
+        .arg(&input)
+        .arg("--output")
+        .arg(&output)
+        .arg("--quiet")
+        .status();
+    let svg = match status {
+        Ok(status) if status.success() => Ok(fs::read_to_string(&output).ok()),
+        Ok(status) => {
+            warn!("mmdc failed to render a mermaid diagram: {status}");
+            Ok(None)
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(e),
+        Err(e) => {
+            warn!("Failed to run mmdc: {e}");
+            Ok(None)
+        }
+    };
+
+    let _ = fs::remove_file(&input);
+    let _ = fs::remove_file(&output);
+
+    svg
+}
+
+/// Inline SVG must not carry the `<?xml ...?>` declaration
+fn strip_xml_declaration(svg: &str) -> &str {
+    svg.find("<svg").map_or(svg, |start| &svg[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_render_diagram_dot() {
+        let dir = temp_dir("diagram_dot");
+        let diagrams = Diagrams::new(dir.path().to_path_buf());
+        let source = "digraph { a -> b }";
+
+        let result = diagrams.render("dot", source).unwrap();
+        let cached = diagrams.render("dot", source).unwrap();
+
+        assert!(result.starts_with("<svg"));
+        assert_eq!(result, cached);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_render_diagram_with_invalid_dot() {
+        let dir = temp_dir("diagram_invalid");
+
+        let result = Diagrams::new(dir.path().to_path_buf()).render("dot", "digraph { a -> ");
+
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_render_mermaid_remembers_missing_command() {
+        let dir = temp_dir("diagram_no_mmdc");
+        let cache_dir = dir.path().join("cache");
+        let diagrams = Diagrams {
+            mermaid_command: "chablo-missing-mmdc",
+            ..Diagrams::new(cache_dir.clone())
+        };
+
+        let first = diagrams.render("mermaid", "graph TD; A-->B");
+        let unavailable = diagrams.mermaid_unavailable.load(Ordering::Relaxed);
+        let left = fs::read_dir(&cache_dir).unwrap().count();
+        fs::remove_dir_all(&cache_dir).unwrap();
+        // Not tried again, so the work directory is not created
+        let second = diagrams.render("mermaid", "graph TD; B-->C");
+
+        assert_eq!(first, None);
+        assert!(unavailable);
+        assert_eq!(left, 0);
+        assert_eq!(second, None);
+        assert!(!cache_dir.exists());
+    }
+
+    #[test]
+    fn test_strip_xml_declaration() {
+        let result = strip_xml_declaration(r#"<?xml version="1.0"?><svg></svg>"#);
+
+        assert_eq!(result, "<svg></svg>");
+    }
+}
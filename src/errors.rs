@@ -1,4 +1,6 @@
 //! Custom errors.
+use std::path::Path;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -15,4 +17,55 @@ pub enum ChabloError {
     ParseIntError(#[from] std::num::ParseIntError),
     #[error("Failed to extract the title")]
     ExtractTitleError,
+    #[error("Failed to encode image: {0}")]
+    PngError(#[from] png::EncodingError),
+    #[error("Failed to process image: {0}")]
+    ImageError(#[from] image::ImageError),
+    #[error("Failed to parse config: {0}")]
+    TomlError(#[from] toml::de::Error),
+    #[error("Failed to serialize JSON: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Failed to serialize TOML: {0}")]
+    TomlSerializeError(#[from] toml::ser::Error),
+    #[error("Failed to render theme template: {0}")]
+    TemplateError(#[from] minijinja::Error),
+    #[error("Template not found: {0}")]
+    TemplateNotFound(String),
+    #[error("Two sections write {0}")]
+    DuplicateOutput(String),
+    #[error("Theme not found: {0}")]
+    ThemeNotFound(String),
+    #[error("Failed to highlight code: {0}")]
+    HighlightError(#[from] syntect::Error),
+    #[error("Failed to bundle {0}")]
+    BundleError(String),
+    #[error("Invalid math: {0}")]
+    MathError(String),
+    #[error("Invalid shortcode: {0}")]
+    ShortcodeError(String),
+    #[error("Refusing to remove {0}, which is not inside the project")]
+    UnsafeClean(String),
+    #[error("Found {0} broken links")]
+    BrokenLinks(usize),
+    #[error("{path}:{line}: {source}")]
+    SourceError {
+        path: String,
+        line: usize,
+        source: Box<ChabloError>,
+    },
+}
+
+impl ChabloError {
+    /// Attach the file to an error raised at a line of its markdown body, `first_line`
+    /// being the line of the file the body starts at
+    pub fn in_file(self, path: &Path, first_line: usize) -> Self {
+        match self {
+            ChabloError::SourceError { line, source, .. } => ChabloError::SourceError {
+                path: path.display().to_string(),
+                line: line + first_line - 1,
+                source,
+            },
+            error => error,
+        }
+    }
 }
@@ -1,79 +1,259 @@
 use anyhow::Result;
-use askama::Template;
 
-use crate::models::{Article, ArticleTemplate, TopPage, TopPageTemplate, BLOG_TITLE, DESCRIPTION};
+use crate::errors::ChabloError;
+use crate::models::{
+    Article, ArticleTemplate, Feed, FeedEntry, FeedTemplate, Page, PageMeta, PageTemplate, Site,
+    TopPage, TopPageTemplate, FEED_LENGTH,
+};
+use crate::theme::Theme;
 
 pub trait Generator<T> {
-    fn generate(self) -> Result<String, askama::Error>;
+    fn generate(self, site: &Site, theme: &Theme) -> Result<String, ChabloError>;
 }
 
 impl Generator<Article> for Article {
-    fn generate(self) -> Result<String, askama::Error> {
+    fn generate(self, site: &Site, theme: &Theme) -> Result<String, ChabloError> {
+        let section = site.section(&self.section);
+        let description = self.excerpt.plain_text();
+        let meta = PageMeta {
+            url: site.absolute_url(&self.path),
+            og_type: "article",
+            image: self.image.as_deref().map(|image| site.absolute_url(image)),
+            published_time: Some(self.date),
+        };
         let result = ArticleTemplate {
             title: &self.title,
-            body: self.body,
+            body: &self.body,
             date: self.date,
-            description: DESCRIPTION,
+            description: &description,
+            meta,
+            menus: site.menus_for(&self.path),
+            site,
+            page: &self,
+        };
+
+        theme.render(&result, &section.template, "article.html")
+    }
+}
+
+impl Generator<Page> for Page {
+    fn generate(self, site: &Site, theme: &Theme) -> Result<String, ChabloError> {
+        let description = self.excerpt.plain_text();
+        let meta = PageMeta {
+            url: site.absolute_url(&self.path),
+            og_type: "website",
+            image: self.image.as_deref().map(|image| site.absolute_url(image)),
+            published_time: None,
+        };
+        let result = PageTemplate {
+            title: &self.title,
+            body: &self.body,
+            description: &description,
+            meta,
+            menus: site.menus_for(&self.path),
+            site,
+            page: &self,
         };
 
-        result.render()
+        theme.render(&result, "page.html", "page.html")
     }
 }
 
 impl Generator<TopPage> for TopPage {
-    fn generate(self) -> Result<String, askama::Error> {
+    fn generate(self, site: &Site, theme: &Theme) -> Result<String, ChabloError> {
+        let section = site.section(&self.section);
         let result = TopPageTemplate {
-            title: BLOG_TITLE,
+            title: section.title.as_deref().unwrap_or(&site.title),
             articles: self.articles,
-            description: DESCRIPTION,
+            description: &site.description,
+            meta: PageMeta {
+                url: site.absolute_url(&section.list_path),
+                og_type: "website",
+                image: None,
+                published_time: None,
+            },
+            menus: site.menus_for(&section.list_path),
+            site,
         };
 
-        result.render()
+        theme.render(&result, &section.list_template, "toppage.html")
+    }
+}
+
+impl Generator<Feed> for Feed {
+    fn generate(self, site: &Site, theme: &Theme) -> Result<String, ChabloError> {
+        let section = site.section(&self.section);
+        let feed_path = section.feed.as_deref().unwrap_or("feed.xml");
+        let entries: Vec<FeedEntry> = self
+            .articles
+            .into_iter()
+            .take(FEED_LENGTH)
+            .map(|article| FeedEntry {
+                url: site.absolute_url(&article.path),
+                title: article.title,
+                date: article.date,
+                summary: article.excerpt,
+            })
+            .collect();
+        let result = FeedTemplate {
+            title: section.title.as_deref().unwrap_or(&site.title),
+            url: site.absolute_url(feed_path),
+            updated: entries
+                .iter()
+                .map(|entry| entry.date)
+                .max()
+                .unwrap_or_default(),
+            entries,
+            site,
+        };
+
+        theme.render(&result, "feed.xml", "feed.xml")
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::{curent_datetime, HtmlBody};
+    use crate::config::Config;
+    use crate::models::{HtmlBody, TocEntry};
+    use crate::test_utils::article;
+    use chrono::NaiveDate;
+
+    fn site() -> Site {
+        Site::new(&Config::default(), &[])
+    }
 
     #[test]
     fn test_generate_article_ok() {
-        let date = curent_datetime();
-        let content = "This is a test".to_string();
-        let path = "filepath.html".to_string();
+        let article = article("This is an article");
 
+        let result = article.generate(&site(), &Theme::Builtin);
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_generate_article_with_toc() {
+        let heading = |level: u8, title: &str| TocEntry {
+            level,
+            id: title.to_string(),
+            title: title.to_string(),
+            children: vec![],
+        };
+        let toc = TocEntry::nest(vec![
+            heading(2, "はじめに"),
+            heading(3, "a"),
+            heading(4, "b"),
+            heading(5, "c&d"),
+        ]);
+
+        let mut article = Article {
+            toc,
+            ..article("This is an article")
+        };
+
+        let result = article.clone().generate(&site(), &Theme::Builtin).unwrap();
+        assert!(!result.contains(r#"<nav class="toc">"#));
+
+        article.extra.insert("toc".to_string(), "true".to_string());
+        let result = article.generate(&site(), &Theme::Builtin).unwrap();
+        assert!(result.contains(r#"<nav class="toc">"#));
+        assert!(result.contains(r##"<a href="#はじめに">はじめに</a>"##));
+        // Every level is rendered, escaped
+        assert!(result.contains(r##"<ol><li><a href="#c&amp;d">c&amp;d</a></li></ol>"##));
+    }
+
+    #[test]
+    fn test_generate_article_meta() {
         let article = Article {
-            id: "id".to_string(),
-            title: "This is an article".to_string(),
-            body: HtmlBody(content),
-            date,
-            path,
+            image: Some("/img/cover.png".to_string()),
+            tags: vec!["rust".to_string()],
+            extra: [("mood".to_string(), "眠い".to_string())].into(),
+            date: NaiveDate::from_ymd_opt(2023, 1, 7).unwrap(),
+            ..article("年末年始")
         };
 
-        let result = article.generate();
+        let result = article.generate(&site(), &Theme::Builtin).unwrap();
 
-        assert!(result.is_ok());
+        assert!(result.contains(r#"<meta property="og:type" content="article">"#));
+        assert!(result.contains("<li>rust</li>"));
+        assert!(result.contains(
+            r#"<meta property="og:image" content="https://blog.chansuke.info/img/cover.png">"#
+        ));
+        assert!(result.contains(r#"<meta property="article:published_time" content="2023-01-07">"#));
+        assert!(result.contains(r#"<meta name="twitter:card" content="summary_large_image">"#));
+        assert!(result.contains(r#"<link rel="canonical" href="https://blog.chansuke.info/%E5%B9%B4%E6%9C%AB%E5%B9%B4%E5%A7%8B.html">"#));
     }
 
     #[test]
-    fn test_generate_toppage_ok() {
-        let date = curent_datetime();
+    fn test_generate_page_ok() {
         let content = "This is a test".to_string();
-        let path = "filepath.html".to_string();
-        let article = Article {
-            id: "test".to_string(),
-            title: "title".to_string(),
-            body: HtmlBody(content),
-            date,
-            path,
+        let page = Page {
+            title: "About".to_string(),
+            body: HtmlBody(content.clone()),
+            excerpt: HtmlBody(content),
+            image: None,
+            extra: Default::default(),
+            path: "about.html".to_string(),
         };
-        let articles: Vec<Article> = vec![article];
 
-        let toppage = TopPage { articles };
+        let result = page.generate(&site(), &Theme::Builtin).unwrap();
 
-        let result = toppage.generate();
+        assert!(result.contains(r#"<meta property="og:type" content="website">"#));
+        assert!(result.contains("<h1>About</h1>"));
+        assert!(!result.contains("<time"));
+    }
+
+    #[test]
+    fn test_generate_toppage_ok() {
+        let articles: Vec<Article> = vec![article("title")];
+
+        let toppage = TopPage {
+            section: "diary".to_string(),
+            articles,
+        };
+
+        let result = toppage.generate(&site(), &Theme::Builtin);
 
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_generate_toppage_with_menus() {
+        let config: Config = toml::from_str(
+            "[[menus]]\nname = \"Home\"\nurl = \"/\"\n\n[[menus]]\nname = \"About\"\nurl = \"/about.html\"",
+        )
+        .unwrap();
+        let site = Site::new(&config, &[]);
+        let toppage = TopPage {
+            section: "diary".to_string(),
+            articles: vec![],
+        };
+
+        let result = toppage.generate(&site, &Theme::Builtin).unwrap();
+
+        assert!(result.contains(r#"<a href="/" aria-current="page">Home</a>"#));
+        assert!(result.contains(r#"<a href="/about.html">About</a>"#));
+    }
+
+    #[test]
+    fn test_generate_feed_ok() {
+        let article = Article {
+            excerpt: HtmlBody("<p>summary</p>".to_string()),
+            date: NaiveDate::from_ymd_opt(2023, 1, 7).unwrap(),
+            ..article("年末年始")
+        };
+        let feed = Feed {
+            section: "diary".to_string(),
+            articles: vec![article],
+        };
+
+        let result = feed.generate(&site(), &Theme::Builtin).unwrap();
+
+        assert!(result.starts_with(r#"<?xml version="1.0" encoding="utf-8"?>"#));
+        assert!(result.contains("<updated>2023-01-07T00:00:00Z</updated>"));
+        assert!(result.contains(r#"<link rel="self" href="https://blog.chansuke.info/feed.xml"/>"#));
+        assert!(result.contains("<title>年末年始</title>"));
+        assert!(result.contains("&#60;p&#62;summary&#60;/p&#62;"));
+    }
 }
@@ -0,0 +1,222 @@
+//! Syntax highlighting of code blocks
+use std::collections::BTreeSet;
+use std::sync::OnceLock;
+
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+use crate::errors::ChabloError;
+
+/// Output path of the generated stylesheet, relative to `public/`
+pub const HIGHLIGHT_CSS_PATH: &str = "css/highlight.css";
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+const HIGHLIGHT_THEME: &str = "InspiredGitHub";
+
+/// Styles of the line numbers and highlighted lines
+const LINE_CSS: &str = r#"
+.highlight .line {
+  display: inline-block;
+  width: 100%;
+}
+.highlight .line::before {
+  content: attr(data-line);
+  display: inline-block;
+  width: 2.5em;
+  margin-right: 1em;
+  text-align: right;
+  opacity: 0.5;
+  user-select: none;
+}
+.highlight .line.highlighted {
+  background-color: rgba(255, 220, 0, 0.25);
+}
+"#;
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Language and highlighted lines of a fenced code block, e.g. ```` ```rust {3,5-7} ````
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CodeInfo {
+    pub lang: String,
+    pub highlighted: BTreeSet<usize>,
+}
+
+impl CodeInfo {
+    pub fn parse(info: &str) -> Self {
+        let (lang, lines) = match info.split_once('{') {
+            Some((lang, lines)) => (lang, lines.trim_end().trim_end_matches('}')),
+            None => (info, ""),
+        };
+        // Only keep the language of info strings such as `rust,ignore`
+        let lang = lang
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| c.is_alphanumeric() || "+-_#.".contains(*c))
+            .collect();
+
+        let mut highlighted = BTreeSet::new();
+        for range in lines.split(',') {
+            let range = range.trim();
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            if let (Ok(start), Ok(end)) = (start.trim().parse::<usize>(), end.trim().parse()) {
+                highlighted.extend(start..=end);
+            }
+        }
+
+        CodeInfo { lang, highlighted }
+    }
+}
+
+/// Render a code block with class-based highlighting, one `<span class="line">` per line
+pub fn highlight(code: &str, info: &CodeInfo) -> Result<String, ChabloError> {
+    let syntax_set = syntax_set();
+    let syntax = syntax_set
+        .find_syntax_by_token(&info.lang)
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntax_set, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator.parse_html_for_line_which_includes_newline(line)?;
+    }
+    let html = generator.finalize();
+
+    let mut output = match info.lang.is_empty() {
+        true => "<pre class=\"highlight hl-code\"><code>".to_string(),
+        false => format!(
+            "<pre class=\"highlight hl-code\"><code class=\"language-{}\">",
+            info.lang
+        ),
+    };
+    for (i, line) in split_lines(&html).iter().enumerate() {
+        let number = i + 1;
+        let class = match info.highlighted.contains(&number) {
+            true => "line highlighted",
+            false => "line",
+        };
+        output.push_str(&format!(
+            "<span class=\"{class}\" data-line=\"{number}\">{line}</span>\n"
+        ));
+    }
+    output.push_str("</code></pre>\n");
+
+    Ok(output)
+}
+
+/// Stylesheet of the highlighting theme and of the line annotations
+pub fn highlight_css() -> Result<String, ChabloError> {
+    let themes = ThemeSet::load_defaults();
+    let css = css_for_theme_with_class_style(&themes.themes[HIGHLIGHT_THEME], CLASS_STYLE)?;
+
+    Ok(css + LINE_CSS)
+}
+
+/// Split highlighted HTML into lines, closing the spans left open at the end of a line
+/// and reopening them on the next one
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut open_spans: Vec<&str> = vec![];
+    let mut line = String::new();
+    let mut has_text = false;
+    let mut rest = html;
+
+    while let Some(ch) = rest.chars().next() {
+        if rest.starts_with("</span>") {
+            open_spans.pop();
+            line.push_str("</span>");
+            rest = &rest["</span>".len()..];
+        } else if rest.starts_with("<span") {
+            let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+            open_spans.push(&rest[..end]);
+            line.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if ch == '\n' {
+            line.push_str(&"</span>".repeat(open_spans.len()));
+            lines.push(line);
+            line = open_spans.concat();
+            has_text = false;
+            rest = &rest[1..];
+        } else {
+            line.push(ch);
+            has_text = true;
+            rest = &rest[ch.len_utf8()..];
+        }
+    }
+    if has_text {
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_code_info() {
+        let result = CodeInfo::parse("rust {3,5-7}");
+
+        assert_eq!(result.lang, "rust");
+        assert_eq!(result.highlighted, BTreeSet::from([3, 5, 6, 7]));
+    }
+
+    #[test]
+    fn test_parse_code_info_without_lines() {
+        let result = CodeInfo::parse("rust,ignore");
+
+        assert_eq!(result.lang, "rust");
+        assert!(result.highlighted.is_empty());
+    }
+
+    #[test]
+    fn test_highlight_ok() {
+        let code = "fn main() {\n    println!(\"hi\");\n}\n";
+        let result = highlight(code, &CodeInfo::parse("rust {2}")).unwrap();
+
+        assert!(
+            result.starts_with(r#"<pre class="highlight hl-code"><code class="language-rust">"#)
+        );
+        assert!(result.contains(r#"<span class="line" data-line="1">"#));
+        assert!(result.contains(r#"<span class="line highlighted" data-line="2">"#));
+        assert!(result.contains(r#"<span class="line" data-line="3">"#));
+        assert!(!result.contains(r#"data-line="4""#));
+        assert!(
+            result.contains(r#"<span class="hl-storage hl-type hl-function hl-rust">fn</span>"#)
+        );
+    }
+
+    #[test]
+    fn test_highlight_unknown_language() {
+        let result = highlight("<b>\n", &CodeInfo::parse("unknown")).unwrap();
+
+        assert!(result.contains("&lt;b&gt;"));
+    }
+
+    #[test]
+    fn test_split_lines_reopens_spans() {
+        let html = "<span class=\"a\">x\ny</span>\n";
+        let result = split_lines(html);
+
+        assert_eq!(
+            result,
+            vec!["<span class=\"a\">x</span>", "<span class=\"a\">y</span>"]
+        );
+    }
+
+    #[test]
+    fn test_highlight_css() {
+        let result = highlight_css().unwrap();
+
+        assert!(result.contains(".hl-"));
+        assert!(result.contains(".highlight .line.highlighted"));
+    }
+}
@@ -0,0 +1,442 @@
+//! Resize the images of posts into responsive variants
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use log::warn;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use crate::config::ImageOptions;
+use crate::errors::ChabloError;
+use crate::links::percent_decode;
+use crate::models::{percent_encode, HtmlBody};
+use crate::og_image::content_hash;
+use crate::writer::Outputs;
+
+/// Directory keeping processed images between builds
+pub const IMAGE_CACHE_DIR: &str = ".cache/images";
+
+/// Bump when the processing changes so that cached variants are regenerated
+const PIPELINE_VERSION: &str = "2";
+
+/// AVIF encoder speed from 1 to 10, trading the file size for the build time
+const AVIF_SPEED: u8 = 8;
+
+fn img_regex() -> &'static Regex {
+    static IMG: OnceLock<Regex> = OnceLock::new();
+    IMG.get_or_init(|| Regex::new(r"<img\s[^>]*>").expect("valid regex"))
+}
+
+fn attribute_regex() -> &'static Regex {
+    static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    ATTRIBUTE.get_or_init(|| Regex::new(r#"([a-z-]+)="([^"]*)""#).expect("valid regex"))
+}
+
+/// Resized copies of an image, from the narrowest
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Variants {
+    /// URL of the image without its extension, e.g. `/img/cat`
+    base_url: String,
+    extension: String,
+    widths: Vec<u32>,
+    width: u32,
+    height: u32,
+    /// Extension of the variants in a more compact format, `webp` or `avif`
+    alternative: &'static str,
+}
+
+impl Variants {
+    fn url(&self, width: u32, extension: &str) -> String {
+        format!("{}-{width}w.{extension}", self.base_url)
+    }
+
+    fn srcset(&self, extension: &str) -> String {
+        self.widths
+            .iter()
+            .map(|width| format!("{} {width}w", self.url(*width, extension)))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    /// `<picture>` replacing `<img>`, keeping its `alt` and `title`
+    fn to_html(&self, attributes: &[(String, String)]) -> String {
+        let sizes = format!("(max-width: {0}px) 100vw, {0}px", self.width);
+        let mut html = String::from("<picture>");
+        html.push_str(&format!(
+            r#"<source type="image/{}" srcset="{}" sizes="{sizes}">"#,
+            self.alternative,
+            self.srcset(self.alternative)
+        ));
+        html.push_str(&format!(
+            r#"<img src="{}" srcset="{}" sizes="{sizes}" width="{}" height="{}""#,
+            self.url(self.width, &self.extension),
+            self.srcset(&self.extension),
+            self.width,
+            self.height
+        ));
+        for (name, value) in attributes {
+            if name == "alt" || name == "title" {
+                html.push_str(&format!(r#" {name}="{value}""#));
+            }
+        }
+        html.push_str(r#" loading="lazy" decoding="async"></picture>"#);
+
+        html
+    }
+}
+
+/// Replace the `<img>` tags of local JPEG and PNG images under `output_dir` with
+/// `<picture>` elements of resized variants, written next to the original and cached in
+/// `cache_dir`. JPEG images get AVIF variants and PNG images lossless WebP ones. The
+/// original is overwritten with a copy without metadata such as EXIF and GPS.
+pub fn process_images(
+    html: &HtmlBody,
+    output_dir: &Path,
+    cache_dir: &Path,
+    options: &ImageOptions,
+    outputs: &mut Outputs,
+) -> Result<HtmlBody, ChabloError> {
+    if !options.enabled {
+        return Ok(html.clone());
+    }
+
+    let mut output = String::new();
+    let mut cursor = 0;
+    for tag in img_regex().find_iter(&html.0) {
+        let attributes: Vec<(String, String)> = attribute_regex()
+            .captures_iter(tag.as_str())
+            .map(|cap| (cap[1].to_string(), cap[2].to_string()))
+            .collect();
+        let src = attributes
+            .iter()
+            .find(|(name, _)| name == "src")
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default();
+
+        let replacement = match local_image(src, output_dir) {
+            Some(path) => match process_image(&path, src, cache_dir, options, outputs) {
+                Ok(variants) => Some(variants.to_html(&attributes)),
+                Err(e) => {
+                    warn!("Failed to process image {}: {e}", path.display());
+                    None
+                }
+            },
+            None => None,
+        };
+        output.push_str(&html.0[cursor..tag.start()]);
+        output.push_str(replacement.as_deref().unwrap_or(tag.as_str()));
+        cursor = tag.end();
+    }
+    output.push_str(&html.0[cursor..]);
+
+    Ok(HtmlBody(output))
+}
+
+/// File of a root relative JPEG or PNG `src`
+fn local_image(src: &str, output_dir: &Path) -> Option<PathBuf> {
+    let path = output_dir.join(percent_decode(src.strip_prefix('/')?));
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+    (["jpg", "jpeg", "png"].contains(&extension.as_str()) && path.is_file()).then_some(path)
+}
+
+/// Write the variants of an image, reusing the ones cached for the same content
+fn process_image(
+    path: &Path,
+    src: &str,
+    cache_root: &Path,
+    options: &ImageOptions,
+    outputs: &mut Outputs,
+) -> Result<Variants, ChabloError> {
+    let content = fs::read(path)?;
+    let digest = format!("{:x}", Sha256::digest(&content));
+    // The original is replaced by its stripped copy, which must map to the same entry
+    let processed = cache_root.join("processed").join(&digest);
+    let digest = fs::read_to_string(&processed).unwrap_or(digest);
+    let widths = format!("{:?}", options.widths);
+    let hash = content_hash(&[
+        PIPELINE_VERSION,
+        &digest,
+        &widths,
+        &options.quality.to_string(),
+    ]);
+    let cache_dir = cache_root.join(hash);
+    let is_png = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    let (extension, alternative) = if is_png {
+        ("png", "webp")
+    } else {
+        ("jpg", "avif")
+    };
+
+    let full = cache_dir.join(format!("full.{extension}"));
+    if !full.is_file() {
+        encode_variants(&content, &cache_dir, extension, alternative, options)?;
+        let stripped = format!("{:x}", Sha256::digest(fs::read(&full)?));
+        fs::create_dir_all(processed.parent().unwrap_or(&cache_dir))?;
+        fs::write(processed.with_file_name(stripped), &digest)?;
+    }
+
+    let (width, height) = image::image_dimensions(&full)?;
+    let mut widths: Vec<u32> = options
+        .widths
+        .iter()
+        .copied()
+        .filter(|w| *w < width)
+        .collect();
+    let largest = options
+        .widths
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(width)
+        .min(width);
+    if !widths.contains(&largest) {
+        widths.push(largest);
+    }
+    widths.sort_unstable();
+
+    // Copy the variants next to the original
+    let stem = path.with_extension("");
+    let mut copy = |name: &str, dest: PathBuf| outputs.copy_file(&cache_dir.join(name), &dest);
+    for width in &widths {
+        copy(
+            &format!("{width}.{extension}"),
+            PathBuf::from(format!("{}-{width}w.{extension}", stem.display())),
+        )?;
+        copy(
+            &format!("{width}.{alternative}"),
+            PathBuf::from(format!("{}-{width}w.{alternative}", stem.display())),
+        )?;
+    }
+    copy(&format!("full.{extension}"), path.to_path_buf())?;
+
+    let base = src.rsplit_once('.').map_or(src, |(base, _)| base);
+    let largest_height = (height as u64 * largest as u64 / width as u64) as u32;
+
+    Ok(Variants {
+        base_url: percent_encode(&percent_decode(base)),
+        extension: extension.to_string(),
+        widths,
+        width: largest,
+        height: largest_height,
+        alternative,
+    })
+}
+
+/// Decode an image, apply its EXIF orientation and write every width into `cache_dir`,
+/// in the format of the original and in the `alternative` one. Encoding from pixels drops
+/// the metadata of the original.
+fn encode_variants(
+    content: &[u8],
+    cache_dir: &Path,
+    extension: &str,
+    alternative: &str,
+    options: &ImageOptions,
+) -> Result<(), ChabloError> {
+    let mut decoder = ImageReader::new(std::io::Cursor::new(content))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    fs::create_dir_all(cache_dir)?;
+    let save = |image: &DynamicImage, name: &str| -> Result<(), ChabloError> {
+        let mut file = BufWriter::new(File::create(cache_dir.join(name))?);
+        match name.rsplit_once('.').map(|(_, ext)| ext) {
+            Some("jpg") => image
+                .to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut file, options.quality))?,
+            Some("webp") => image
+                .to_rgba8()
+                .write_with_encoder(WebPEncoder::new_lossless(&mut file))?,
+            Some("avif") => {
+                image
+                    .to_rgb8()
+                    .write_with_encoder(AvifEncoder::new_with_speed_quality(
+                        &mut file,
+                        AVIF_SPEED,
+                        options.quality,
+                    ))?
+            }
+            _ => image.write_to(&mut file, ImageFormat::Png)?,
+        }
+
+        Ok(())
+    };
+
+    for width in options.widths.iter().copied().chain([image.width()]) {
+        let resized = match width < image.width() {
+            true => image.resize(width, u32::MAX, FilterType::Lanczos3),
+            false => image.clone(),
+        };
+        let width = width.min(image.width());
+        save(&resized, &format!("{width}.{extension}"))?;
+        save(&resized, &format!("{width}.{alternative}"))?;
+    }
+    // Written last, marking the cache entry as complete
+    save(&image, &format!("full.{extension}"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+    use image::{Rgb, RgbImage};
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    fn options() -> ImageOptions {
+        ImageOptions {
+            widths: vec![100, 200],
+            ..ImageOptions::default()
+        }
+    }
+
+    /// JPEG of 300x150 with an EXIF segment inserted after its SOI marker
+    fn jpeg_with_exif() -> Vec<u8> {
+        let image = RgbImage::from_fn(300, 150, |x, _| Rgb([(x % 256) as u8, 80, 160]));
+        let mut jpeg = vec![];
+        image
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, 90))
+            .unwrap();
+
+        let exif: &[u8] = b"Exif\0\0II*\0\x08\0\0\0\0\0\0\0\0\0";
+        let length = (exif.len() + 2) as u16;
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&length.to_be_bytes());
+        segment.extend_from_slice(exif);
+        jpeg.splice(2..2, segment);
+
+        jpeg
+    }
+
+    /// Output and cache directories of a test, under a directory of its own
+    fn dirs(name: &str) -> (TempDir, PathBuf, PathBuf) {
+        let dir = temp_dir(name);
+        let output_dir = dir.path().join("public");
+        fs::create_dir_all(&output_dir).unwrap();
+        let cache_dir = dir.path().join("cache");
+
+        (dir, output_dir, cache_dir)
+    }
+
+    #[test]
+    fn test_process_images_jpeg() {
+        let (_dir, output_dir, cache_dir) = dirs("images_jpeg");
+        fs::create_dir_all(output_dir.join("花見")).unwrap();
+        fs::write(output_dir.join("花見/桜.jpg"), jpeg_with_exif()).unwrap();
+        let src = "/%E8%8A%B1%E8%A6%8B/%E6%A1%9C";
+        let html = HtmlBody(format!(
+            r#"<p><img src="{src}.jpg" alt="桜" /><img src="https://example.com/a.jpg" alt="x" /></p>"#
+        ));
+
+        let result = process_images(
+            &html,
+            &output_dir,
+            &cache_dir,
+            &options(),
+            &mut Outputs::default(),
+        )
+        .unwrap();
+        let original = fs::read(output_dir.join("花見/桜.jpg")).unwrap();
+        let variant = image::image_dimensions(output_dir.join("花見/桜-100w.jpg")).unwrap();
+        let avif = image::ImageReader::open(output_dir.join("花見/桜-200w.avif"))
+            .unwrap()
+            .with_guessed_format()
+            .unwrap()
+            .format();
+
+        assert_eq!(
+            result.0,
+            format!(
+                r#"<p><picture><source type="image/avif" srcset="{src}-100w.avif 100w, {src}-200w.avif 200w" sizes="(max-width: 200px) 100vw, 200px"><img src="{src}-200w.jpg" srcset="{src}-100w.jpg 100w, {src}-200w.jpg 200w" sizes="(max-width: 200px) 100vw, 200px" width="200" height="100" alt="桜" loading="lazy" decoding="async"></picture><img src="https://example.com/a.jpg" alt="x" /></p>"#
+            )
+        );
+        assert_eq!(variant, (100, 50));
+        assert_eq!(avif, Some(ImageFormat::Avif));
+        assert!(!original.windows(4).any(|window| window == b"Exif"));
+    }
+
+    #[test]
+    fn test_process_images_png_with_webp() {
+        let (_dir, output_dir, cache_dir) = dirs("images_png");
+        RgbImage::new(150, 100)
+            .save(output_dir.join("diagram.png"))
+            .unwrap();
+        let html = HtmlBody(r#"<img src="/diagram.png" alt="" />"#.to_string());
+
+        let result = process_images(
+            &html,
+            &output_dir,
+            &cache_dir,
+            &options(),
+            &mut Outputs::default(),
+        )
+        .unwrap();
+        let webp = output_dir.join("diagram-150w.webp").is_file();
+
+        assert!(result.0.starts_with(
+            r#"<picture><source type="image/webp" srcset="/diagram-100w.webp 100w, /diagram-150w.webp 150w""#
+        ));
+        assert!(result.0.contains(r#"width="150" height="100""#));
+        assert!(webp);
+    }
+
+    #[test]
+    fn test_process_images_reuses_stripped_original() {
+        let (_dir, output_dir, cache_dir) = dirs("images_again");
+        fs::write(output_dir.join("again.jpg"), jpeg_with_exif()).unwrap();
+        let html = HtmlBody(r#"<img src="/again.jpg" alt="" />"#.to_string());
+
+        let process = || {
+            process_images(
+                &html,
+                &output_dir,
+                &cache_dir,
+                &options(),
+                &mut Outputs::default(),
+            )
+            .unwrap()
+        };
+
+        let first = process();
+        let stripped = fs::read(output_dir.join("again.jpg")).unwrap();
+        let second = process();
+        let result = fs::read(output_dir.join("again.jpg")).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(result, stripped);
+    }
+
+    #[test]
+    fn test_process_images_disabled() {
+        let html = HtmlBody(r#"<img src="/missing.jpg" alt="" />"#.to_string());
+        let options = ImageOptions {
+            enabled: false,
+            ..ImageOptions::default()
+        };
+
+        let result = process_images(
+            &html,
+            Path::new("public"),
+            Path::new("cache"),
+            &options,
+            &mut Outputs::default(),
+        )
+        .unwrap();
+
+        assert_eq!(result, html);
+    }
+}
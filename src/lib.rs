@@ -1,17 +1,49 @@
+pub mod assets;
 pub mod builder;
+pub mod bundle;
+pub mod bundler;
 pub mod cli;
+pub mod compress;
+pub mod config;
+pub mod diagram;
 pub mod errors;
 pub mod generator;
+pub mod highlight;
+pub mod images;
+pub mod link_checker;
+pub mod links;
+pub mod math;
+pub mod minify;
 pub mod models;
+pub mod og_image;
 pub mod parser;
 pub mod server;
+pub mod shortcode;
+#[cfg(test)]
+mod test_utils;
+pub mod theme;
 pub mod writer;
 
+pub use crate::assets::*;
 pub use crate::builder::*;
+pub use crate::bundle::*;
+pub use crate::bundler::*;
 pub use crate::cli::*;
+pub use crate::compress::*;
+pub use crate::config::*;
+pub use crate::diagram::*;
 pub use crate::errors::*;
 pub use crate::generator::*;
+pub use crate::highlight::*;
+pub use crate::images::*;
+pub use crate::link_checker::*;
+pub use crate::links::*;
+pub use crate::math::*;
+pub use crate::minify::*;
 pub use crate::models::*;
+pub use crate::og_image::*;
 pub use crate::parser::*;
 pub use crate::server::*;
+pub use crate::shortcode::*;
+pub use crate::theme::*;
 pub use crate::writer::*;
@@ -0,0 +1,408 @@
+//! Check the outbound links of the built site over HTTP
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Local, NaiveDateTime};
+use glob::glob;
+use serde::{Deserialize, Serialize};
+use ureq::Agent;
+
+use crate::builder::OUTPUT_DIR;
+use crate::config::Config;
+use crate::errors::ChabloError;
+use crate::links::extract_values;
+use crate::writer::write;
+
+/// Results of earlier checks, reused until they expire
+pub const LINK_CACHE_PATH: &str = ".cache/link-check.toml";
+
+const USER_AGENT: &str = concat!("chablo/", env!("CARGO_PKG_VERSION"), " (link checker)");
+
+#[derive(Clone, Debug)]
+pub struct LinkCheckOptions {
+    /// Number of requests in flight at once
+    pub concurrency: usize,
+    pub timeout: Duration,
+    /// How long a cached result stays valid, `None` disabling the cache
+    pub max_age: Option<chrono::Duration>,
+}
+
+impl Default for LinkCheckOptions {
+    fn default() -> Self {
+        LinkCheckOptions {
+            concurrency: 8,
+            timeout: Duration::from_secs(10),
+            max_age: Some(chrono::Duration::days(7)),
+        }
+    }
+}
+
+/// Outcome of requesting a URL
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum LinkStatus {
+    Ok {
+        code: u16,
+    },
+    Redirect {
+        code: u16,
+        location: String,
+    },
+    Broken {
+        code: u16,
+    },
+    /// Connection failures and timeouts, which are never cached
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    checked_at: NaiveDateTime,
+    status: LinkStatus,
+}
+
+/// Status of an outbound link with the generated files linking to it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkReport {
+    pub url: String,
+    pub status: LinkStatus,
+    pub files: BTreeSet<PathBuf>,
+}
+
+/// Entry point of `chablo check-links`: check the links of `public/` and print a report
+/// of the redirects and broken links. Fails when a link is broken.
+pub fn run_check_links(options: &LinkCheckOptions) -> Result<(), ChabloError> {
+    let config = Config::load()?;
+    let cache_path = options.max_age.map(|_| Path::new(LINK_CACHE_PATH));
+    let reports =
+        check_external_links(Path::new(OUTPUT_DIR), &config.base_url, options, cache_path)?;
+
+    let mut redirects = 0;
+    let mut broken = 0;
+    for report in &reports {
+        match &report.status {
+            LinkStatus::Ok { .. } => continue,
+            LinkStatus::Redirect { code, location } => {
+                redirects += 1;
+                println!("{code} {} -> {location}", report.url);
+            }
+            LinkStatus::Broken { code } => {
+                broken += 1;
+                println!("{code} {}", report.url);
+            }
+            LinkStatus::Error { message } => {
+                broken += 1;
+                println!("error {}: {message}", report.url);
+            }
+        }
+        for file in &report.files {
+            println!("    {}", file.display());
+        }
+    }
+    println!(
+        "Checked {} links: {redirects} redirected, {broken} broken",
+        reports.len()
+    );
+
+    match broken {
+        0 => Ok(()),
+        _ => Err(ChabloError::BrokenLinks(broken)),
+    }
+}
+
+/// Check every `http(s)` link of the HTML files under `output_dir`, except the ones to
+/// the site itself
+pub fn check_external_links(
+    output_dir: &Path,
+    base_url: &str,
+    options: &LinkCheckOptions,
+    cache_path: Option<&Path>,
+) -> Result<Vec<LinkReport>, ChabloError> {
+    let links = collect_external_links(output_dir, base_url)?;
+    let now = Local::now().naive_local();
+    let mut cache = match cache_path {
+        Some(path) => load_cache(path),
+        None => BTreeMap::new(),
+    };
+
+    let is_fresh = |entry: &CacheEntry| {
+        options
+            .max_age
+            .is_some_and(|max_age| entry.checked_at + max_age > now)
+    };
+    let unchecked: Vec<String> = links
+        .keys()
+        .filter(|url| !cache.get(*url).is_some_and(is_fresh))
+        .cloned()
+        .collect();
+    for (url, status) in check_urls(unchecked, options) {
+        cache.insert(
+            url,
+            CacheEntry {
+                checked_at: now,
+                status,
+            },
+        );
+    }
+
+    if let Some(path) = cache_path {
+        save_cache(path, &cache)?;
+    }
+
+    Ok(links
+        .into_iter()
+        .map(|(url, files)| LinkReport {
+            status: cache[&url].status.clone(),
+            url,
+            files,
+        })
+        .collect())
+}
+
+/// Outbound URLs mapped to the files linking to them
+fn collect_external_links(
+    output_dir: &Path,
+    base_url: &str,
+) -> Result<BTreeMap<String, BTreeSet<PathBuf>>, ChabloError> {
+    let pattern = format!("{}/**/*.html", output_dir.display());
+    let mut links: BTreeMap<String, BTreeSet<PathBuf>> = BTreeMap::new();
+
+    for file in glob(&pattern)?.flatten() {
+        let html = fs::read_to_string(&file)?;
+        for link in extract_values(&html, &[" href=\"", " src=\""]) {
+            let is_http = link.starts_with("http://") || link.starts_with("https://");
+            let is_own = !base_url.is_empty() && link.starts_with(base_url);
+            if !is_http || is_own {
+                continue;
+            }
+            links.entry(link).or_default().insert(file.clone());
+        }
+    }
+
+    Ok(links)
+}
+
+/// Request the URLs with at most `options.concurrency` requests at once
+fn check_urls(urls: Vec<String>, options: &LinkCheckOptions) -> BTreeMap<String, LinkStatus> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(options.timeout))
+        .max_redirects(0)
+        .http_status_as_error(false)
+        .user_agent(USER_AGENT)
+        .build()
+        .into();
+    let queue = Mutex::new(urls.into_iter());
+    let results = Mutex::new(BTreeMap::new());
+
+    thread::scope(|scope| {
+        for _ in 0..options.concurrency.max(1) {
+            scope.spawn(|| loop {
+                let Some(url) = queue.lock().expect("queue lock").next() else {
+                    break;
+                };
+                let status = check_url(&agent, &url);
+                results.lock().expect("results lock").insert(url, status);
+            });
+        }
+    });
+
+    results.into_inner().expect("results lock")
+}
+
+/// Request a URL with `HEAD`, falling back to `GET` for servers rejecting it
+fn check_url(agent: &Agent, url: &str) -> LinkStatus {
+    let response = match agent.head(url).call() {
+        Ok(response) if matches!(response.status().as_u16(), 405 | 501) => agent.get(url).call(),
+        response => response,
+    };
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            return LinkStatus::Error {
+                message: e.to_string(),
+            }
+        }
+    };
+
+    let code = response.status().as_u16();
+    match code {
+        300..=399 => LinkStatus::Redirect {
+            code,
+            location: response
+                .headers()
+                .get("location")
+                .and_then(|location| location.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+        },
+        400.. => LinkStatus::Broken { code },
+        _ => LinkStatus::Ok { code },
+    }
+}
+
+fn load_cache(path: &Path) -> BTreeMap<String, CacheEntry> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| toml::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Save the cache without the errors, which are worth retrying
+fn save_cache(path: &Path, cache: &BTreeMap<String, CacheEntry>) -> Result<(), ChabloError> {
+    let cache: BTreeMap<&String, &CacheEntry> = cache
+        .iter()
+        .filter(|(_, entry)| !matches!(entry.status, LinkStatus::Error { .. }))
+        .collect();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    write(toml::to_string(&cache)?, &path.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::test_utils::temp_dir;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    /// Stand-in HTTP server answering `/ok`, `/moved`, `/no-head` and 404 otherwise,
+    /// returning its base URL and the number of requests served
+    fn serve() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default();
+                let response = match (method, parts.next().unwrap_or_default()) {
+                    (_, "/ok") => "HTTP/1.1 200 OK\r\n".to_string(),
+                    (_, "/moved") => {
+                        "HTTP/1.1 301 Moved Permanently\r\nLocation: /ok\r\n".to_string()
+                    }
+                    ("HEAD", "/no-head") => "HTTP/1.1 405 Method Not Allowed\r\n".to_string(),
+                    ("GET", "/no-head") => "HTTP/1.1 200 OK\r\n".to_string(),
+                    _ => "HTTP/1.1 404 Not Found\r\n".to_string(),
+                };
+                let _ = (&stream).write_all(
+                    format!("{response}Content-Length: 0\r\nConnection: close\r\n\r\n").as_bytes(),
+                );
+            }
+        });
+
+        (base_url, requests)
+    }
+
+    fn output_dir(name: &str, base_url: &str) -> TempDir {
+        let output_dir = temp_dir(name);
+        fs::write(
+            output_dir.path().join("a.html"),
+            format!(
+                r#"<a href="{base_url}/ok">ok</a><a href="{base_url}/moved">moved</a><a href="https://blog.example.com/self.html">self</a>"#
+            ),
+        )
+        .unwrap();
+        fs::write(
+            output_dir.path().join("b.html"),
+            format!(
+                r#"<a href="{base_url}/gone">gone</a><img src="{base_url}/no-head"><a href="/local.html">local</a>"#
+            ),
+        )
+        .unwrap();
+
+        output_dir
+    }
+
+    #[test]
+    fn test_check_external_links() {
+        let (base_url, _) = serve();
+        let output_dir = output_dir("link_checker", &base_url);
+
+        let result = check_external_links(
+            output_dir.path(),
+            "https://blog.example.com",
+            &LinkCheckOptions::default(),
+            None,
+        )
+        .unwrap();
+
+        let result: Vec<(String, LinkStatus, usize)> = result
+            .into_iter()
+            .map(|report| {
+                let url = report.url.replace(&base_url, "");
+                (url, report.status, report.files.len())
+            })
+            .collect();
+        assert_eq!(
+            result,
+            vec![
+                ("/gone".to_string(), LinkStatus::Broken { code: 404 }, 1),
+                (
+                    "/moved".to_string(),
+                    LinkStatus::Redirect {
+                        code: 301,
+                        location: "/ok".to_string()
+                    },
+                    1
+                ),
+                ("/no-head".to_string(), LinkStatus::Ok { code: 200 }, 1),
+                ("/ok".to_string(), LinkStatus::Ok { code: 200 }, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_external_links_with_cache() {
+        let (base_url, requests) = serve();
+        let dir = output_dir("link_checker_cache", &base_url);
+        let output_dir = dir.path();
+        let cache_path = output_dir.join("cache.toml");
+        let options = LinkCheckOptions {
+            concurrency: 2,
+            ..LinkCheckOptions::default()
+        };
+
+        let first = check_external_links(output_dir, "", &options, Some(&cache_path)).unwrap();
+        let served = requests.load(Ordering::SeqCst);
+        let second = check_external_links(output_dir, "", &options, Some(&cache_path)).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(served, 5);
+        assert_eq!(requests.load(Ordering::SeqCst), served);
+    }
+
+    #[test]
+    fn test_check_url_with_connection_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+
+        let result = check_urls(vec![url.clone()], &LinkCheckOptions::default());
+
+        assert!(matches!(result[&url], LinkStatus::Error { .. }));
+    }
+}
@@ -0,0 +1,297 @@
+//! Internal `@/` links and checking of the links between generated files
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glob::glob;
+
+use crate::errors::ChabloError;
+use crate::models::{percent_encode, HtmlBody};
+
+/// Prefix of links to another markdown file, e.g. `@/diary/2023/01/07.md#anchor`
+pub const INTERNAL_LINK_PREFIX: &str = "@/";
+
+const LINK_ATTRIBUTES: [&str; 2] = [" href=\"", " src=\""];
+
+/// Output paths of the markdown sources, for resolving `@/` links
+#[derive(Debug, Default)]
+pub struct InternalLinks {
+    urls: HashMap<String, String>,
+}
+
+impl InternalLinks {
+    /// Register the markdown file `source` as generating `output_path` under `public/`
+    pub fn insert(&mut self, source: &Path, output_path: &str) {
+        let source = source.to_string_lossy().replace('\\', "/");
+        self.urls.insert(source, output_path.to_string());
+    }
+
+    /// Replace `@/` links with the URL of the generated page. Unknown targets are kept
+    /// as is, so that the link check reports them.
+    pub fn resolve(&self, html: &HtmlBody) -> HtmlBody {
+        let mut output = String::new();
+        let mut rest = html.0.as_str();
+        let marker = format!(" href=\"{INTERNAL_LINK_PREFIX}");
+
+        while let Some(start) = rest.find(&marker) {
+            let value_start = start + " href=\"".len();
+            let Some(length) = rest[value_start..].find('"') else {
+                break;
+            };
+            let link = &rest[value_start..value_start + length];
+            output.push_str(&rest[..value_start]);
+            output.push_str(&self.url(link).unwrap_or_else(|| link.to_string()));
+            rest = &rest[value_start + length..];
+        }
+        output.push_str(rest);
+
+        HtmlBody(output)
+    }
+
+    fn url(&self, link: &str) -> Option<String> {
+        let link = link.strip_prefix(INTERNAL_LINK_PREFIX)?;
+        let (source, anchor) = match link.split_once('#') {
+            Some((source, anchor)) => (source, format!("#{anchor}")),
+            None => (link, String::new()),
+        };
+        let path = self.urls.get(&percent_decode(source))?;
+
+        Some(format!("/{}{}", percent_encode(path), anchor))
+    }
+}
+
+/// Link of a generated page pointing to nothing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// Generated file containing the link
+    pub file: PathBuf,
+    pub link: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for BrokenLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: `{}` ({})",
+            self.file.display(),
+            self.link,
+            self.reason
+        )
+    }
+}
+
+/// Check the local links and anchors of every HTML file under `output_dir`
+pub fn check_links(output_dir: &Path) -> Result<Vec<BrokenLink>, ChabloError> {
+    let pattern = format!("{}/**/*.html", output_dir.display());
+    let mut ids: HashMap<PathBuf, BTreeSet<String>> = HashMap::new();
+    let mut broken: Vec<BrokenLink> = vec![];
+
+    for file in glob(&pattern)?.flatten() {
+        let html = fs::read_to_string(&file)?;
+
+        for link in extract_values(&html, &LINK_ATTRIBUTES) {
+            if is_external(&link) {
+                continue;
+            }
+            let (path, anchor) = link.split_once('#').unwrap_or((&link, ""));
+            let path = path.split('?').next().unwrap_or_default();
+            let target = match path {
+                "" => file.clone(),
+                _ if path.starts_with('/') => output_dir.join(percent_decode(&path[1..])),
+                _ => file
+                    .parent()
+                    .unwrap_or(output_dir)
+                    .join(percent_decode(path)),
+            };
+            let target = if target.is_dir() {
+                target.join("index.html")
+            } else {
+                target
+            };
+
+            let reason = if link.starts_with(INTERNAL_LINK_PREFIX) {
+                Some("unknown markdown file")
+            } else if !target.is_file() {
+                Some("missing file")
+            } else if !anchor.is_empty() && target.extension().is_some_and(|ext| ext == "html") {
+                if !ids.contains_key(&target) {
+                    let target_html = fs::read_to_string(&target)?;
+                    ids.insert(target.clone(), extract_values(&target_html, &[" id=\""]));
+                }
+                (!ids[&target].contains(&percent_decode(anchor))).then_some("missing anchor")
+            } else {
+                None
+            };
+
+            if let Some(reason) = reason {
+                broken.push(BrokenLink {
+                    file: file.clone(),
+                    link,
+                    reason,
+                });
+            }
+        }
+    }
+
+    Ok(broken)
+}
+
+/// Point the `#anchor` links of an excerpt at the page of its article, as the listings
+/// showing the excerpt do not have the ids of its headings
+pub fn link_anchors_to(excerpt: &HtmlBody, path: &str) -> HtmlBody {
+    let url = format!(" href=\"/{}#", percent_encode(path));
+
+    HtmlBody(excerpt.0.replace(" href=\"#", &url))
+}
+
+/// Values of the given attributes, such as ` href="`, with `&amp;` unescaped
+pub(crate) fn extract_values(html: &str, attributes: &[&str]) -> BTreeSet<String> {
+    let mut values = BTreeSet::new();
+
+    for attribute in attributes {
+        for (start, _) in html.match_indices(attribute) {
+            let value_start = start + attribute.len();
+            if let Some(length) = html[value_start..].find('"') {
+                values.insert(html[value_start..value_start + length].replace("&amp;", "&"));
+            }
+        }
+    }
+
+    values
+}
+
+/// Links with a scheme such as `https:` or `mailto:`, or protocol relative ones
+fn is_external(link: &str) -> bool {
+    if link.starts_with("//") {
+        return true;
+    }
+
+    match link.split_once(':') {
+        Some((scheme, _)) => {
+            !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || "+-.".contains(ch))
+        }
+        None => false,
+    }
+}
+
+/// Decode `%XX` sequences, keeping invalid ones as is
+pub(crate) fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded: Vec<u8> = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_resolve_internal_links() {
+        let mut links = InternalLinks::default();
+        links.insert(Path::new("diary/2023/01/07.md"), "年末年始.html");
+        let html = HtmlBody(
+            r#"<a href="@/diary/2023/01/07.md#%E5%88%9D">a</a> <a href="@/diary/missing.md">b</a>"#
+                .to_string(),
+        );
+
+        let result = links.resolve(&html);
+
+        assert_eq!(
+            result.0,
+            r#"<a href="/%E5%B9%B4%E6%9C%AB%E5%B9%B4%E5%A7%8B.html#%E5%88%9D">a</a> <a href="@/diary/missing.md">b</a>"#
+        );
+    }
+
+    #[test]
+    fn test_link_anchors_to() {
+        let html =
+            HtmlBody(r##"<a href="#%E5%88%9D">a</a> <a href="/b.html#c">b</a>"##.to_string());
+
+        let result = link_anchors_to(&html, "2023/年末.html");
+
+        assert_eq!(
+            result.0,
+            r##"<a href="/2023/%E5%B9%B4%E6%9C%AB.html#%E5%88%9D">a</a> <a href="/b.html#c">b</a>"##
+        );
+    }
+
+    #[test]
+    fn test_check_links() {
+        let dir = temp_dir("check_links");
+        let output_dir = dir.path();
+        fs::create_dir_all(output_dir.join("img")).unwrap();
+        fs::write(output_dir.join("img/cat.png"), "").unwrap();
+        fs::write(
+            output_dir.join("年末年始.html"),
+            r#"<h2 id="初詣">初詣</h2><a href="/index.html#top">top</a>"#,
+        )
+        .unwrap();
+        fs::write(
+            output_dir.join("index.html"),
+            concat!(
+                r#"<a id="top" href="/%E5%B9%B4%E6%9C%AB%E5%B9%B4%E5%A7%8B.html#%E5%88%9D%E8%A9%A3">ok</a>"#,
+                r#"<img src="img/cat.png"><a href="https://example.com/">external</a>"#,
+                r##"<a href="/gone.html">gone</a><a href="#nowhere">anchor</a>"##,
+                r#"<a href="@/diary/missing.md">missing</a>"#,
+            ),
+        )
+        .unwrap();
+
+        let result = check_links(output_dir).unwrap();
+
+        let result: Vec<(&str, &str)> = result
+            .iter()
+            .map(|broken| (broken.link.as_str(), broken.reason))
+            .collect();
+        assert_eq!(
+            result,
+            vec![
+                ("#nowhere", "missing anchor"),
+                ("/gone.html", "missing file"),
+                ("@/diary/missing.md", "unknown markdown file"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_external() {
+        assert!(is_external("https://example.com"));
+        assert!(is_external("mailto:me@example.com"));
+        assert!(is_external("//cdn.example.com/a.js"));
+        assert!(!is_external("/css/main.css"));
+        assert!(!is_external("@/diary/a.md"));
+        assert!(!is_external("a.html#b:c"));
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("%E5%B9%B4.html"), "年.html");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+}
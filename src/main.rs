@@ -1,6 +1,9 @@
-use chablo::builder::build;
+use std::time::Duration;
+
+use chablo::builder::{build, build_with, clean, BuildMode};
 use chablo::cli::{ChabloOpt, Commands};
 use chablo::errors::ChabloError;
+use chablo::link_checker::{run_check_links, LinkCheckOptions};
 use chablo::server::serve;
 
 fn main() -> Result<(), ChabloError> {
@@ -10,6 +13,17 @@ fn main() -> Result<(), ChabloError> {
     match args.command {
         Commands::Convert { file: _ } => Ok(()),
         Commands::Build => build(),
-        Commands::Serve => serve(),
+        Commands::Serve => build_with(BuildMode::Development).and_then(|_| serve()),
+        Commands::Clean => clean(),
+        Commands::CheckLinks {
+            concurrency,
+            timeout,
+            cache_days,
+            no_cache,
+        } => run_check_links(&LinkCheckOptions {
+            concurrency,
+            timeout: Duration::from_secs(timeout),
+            max_age: (!no_cache).then(|| chrono::Duration::days(cache_days)),
+        }),
     }
 }
@@ -0,0 +1,61 @@
+//! Render LaTeX math into MathML at build time
+use pulldown_latex::config::DisplayMode;
+use pulldown_latex::{push_mathml, Parser, RenderConfig, Storage};
+
+use crate::errors::ChabloError;
+
+/// Render `$...$` (inline) or `$$...$$` (display) math. Invalid TeX is an error rather
+/// than being rendered in place, so that broken formulas never reach the output.
+pub fn render_math(tex: &str, display: bool) -> Result<String, ChabloError> {
+    let storage = Storage::new();
+    let events = Parser::new(tex, &storage)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| ChabloError::MathError(error.to_string()))?;
+
+    let config = RenderConfig {
+        display_mode: if display {
+            DisplayMode::Block
+        } else {
+            DisplayMode::Inline
+        },
+        annotation: Some(tex),
+        ..Default::default()
+    };
+    let mut mathml = String::new();
+    push_mathml(
+        &mut mathml,
+        events.into_iter().map(Ok::<_, std::convert::Infallible>),
+        config,
+    )?;
+
+    Ok(mathml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_math_inline() {
+        let result = render_math("x^2", false).unwrap();
+
+        assert!(result.starts_with("<math"));
+        assert!(!result.contains(r#"display="block""#));
+        assert!(result.contains("<msup>"));
+    }
+
+    #[test]
+    fn test_render_math_display() {
+        let result = render_math(r"\frac{a}{b}", true).unwrap();
+
+        assert!(result.contains(r#"display="block""#));
+        assert!(result.contains("<mfrac>"));
+    }
+
+    #[test]
+    fn test_render_math_with_invalid_tex() {
+        let result = render_math(r"\frac{a}", false);
+
+        assert!(matches!(result, Err(ChabloError::MathError(_))));
+    }
+}
@@ -0,0 +1,360 @@
+//! Strip the indentation and comments of generated HTML
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// Elements whose content is kept verbatim
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Elements around which whitespace does not render, and so can be dropped
+const BLOCK_ELEMENTS: [&str; 46] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "caption",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+];
+
+/// Minify a page: comments are removed, runs of whitespace in text collapse into one
+/// space, and whitespace between block elements is dropped. The content of `<pre>`,
+/// `<textarea>`, `<script>` and `<style>` elements is left untouched.
+pub fn minify_html(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    // Whether the last tag written belongs to a block element, or the start of the page
+    let mut previous_block = true;
+    // Whitespace between tags, written unless a block element is next to it
+    let mut pending_space = false;
+    // Whether the last text written ends with a space, dropped before a block element
+    let mut trailing_space = false;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(tag) = tag_at(rest) else {
+            // Text up to the next tag, a stray `<` being part of it
+            let first = rest.chars().next().map_or(1, char::len_utf8);
+            let end = rest[first..].find('<').map_or(rest.len(), |i| i + first);
+            let text = collapse_whitespace(&rest[..end]);
+            rest = &rest[end..];
+            // Only ASCII whitespace collapses, not no-break spaces
+            if text.trim_matches(' ').is_empty() {
+                pending_space |= !text.is_empty();
+                continue;
+            }
+            let text = if previous_block {
+                text.trim_start_matches(' ')
+            } else if pending_space && !text.starts_with(' ') {
+                minified.push(' ');
+                &text
+            } else {
+                &text
+            };
+            pending_space = false;
+            trailing_space = text.ends_with(' ');
+            minified.push_str(text);
+            previous_block = false;
+            continue;
+        };
+
+        let is_block = tag
+            .name
+            .as_deref()
+            .is_some_and(|name| BLOCK_ELEMENTS.contains(&name));
+        if is_block && trailing_space {
+            minified.pop();
+        } else if pending_space && !previous_block && !is_block {
+            minified.push(' ');
+        }
+        pending_space = false;
+        trailing_space = false;
+        previous_block = is_block;
+        minified.push_str(&rest[..tag.len]);
+        rest = &rest[tag.len..];
+
+        if let Some(name) = tag
+            .name
+            .filter(|name| !tag.closing && RAW_ELEMENTS.contains(&name.as_str()))
+        {
+            let end = find_closing_tag(rest, &name).unwrap_or(rest.len());
+            minified.push_str(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+
+    minified
+}
+
+/// Tag at the start of the input
+struct Tag {
+    /// Lowercase element name, `None` for a doctype
+    name: Option<String>,
+    closing: bool,
+    /// Length of the tag in bytes, up to the closing `>`
+    len: usize,
+}
+
+fn tag_at(input: &str) -> Option<Tag> {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    let tag = TAG.get_or_init(|| {
+        Regex::new(r#"^<(/?)([a-zA-Z][a-zA-Z0-9-]*|![a-zA-Z]+)(?:[^>"']|"[^"]*"|'[^']*')*>"#)
+            .expect("valid regex")
+    });
+    let captures = tag.captures(input)?;
+    let name = &captures[2];
+
+    Some(Tag {
+        name: (!name.starts_with('!')).then(|| name.to_ascii_lowercase()),
+        closing: !captures[1].is_empty(),
+        len: captures[0].len(),
+    })
+}
+
+/// Offset of `</name` in the input, ignoring the case
+fn find_closing_tag(input: &str, name: &str) -> Option<usize> {
+    let closing = format!("</{name}");
+
+    input.char_indices().map(|(i, _)| i).find(|&i| {
+        input
+            .get(i..i + closing.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(&closing))
+    })
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !in_whitespace {
+                collapsed.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            collapsed.push(c);
+            in_whitespace = false;
+        }
+    }
+
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::generator::Generator;
+    use crate::models::{Article, HtmlBody, Site, TopPage};
+    use crate::test_utils;
+    use crate::theme::Theme;
+    use pretty_assertions::assert_eq;
+    use scraper::{ElementRef, Html, Node};
+
+    const BODY: &str = r##"<h2 id="code">Code <a class="anchor" href="#code" aria-label="#"></a></h2>
+<p>An <em>inline</em> <strong>element</strong>,&nbsp;and
+   a <a href="/x.html">link</a>.</p>
+<!-- a comment -->
+<pre class="highlight"><code><span class="line" data-line="1">fn main() {
+</span><span class="line" data-line="2">    println!("  spaced  ");
+</span>}</code></pre>
+<script>
+  if (1 < 2 && "</p>") {
+    console.log("  kept  ");
+  }
+</script>
+<ul>
+  <li>one</li>
+  <li><code>two  spaces</code></li>
+</ul>
+"##;
+
+    fn article() -> Article {
+        Article {
+            body: HtmlBody(BODY.to_string()),
+            tags: vec!["rust".to_string()],
+            ..test_utils::article("最小化")
+        }
+    }
+
+    /// Elements, attributes and text of the document, ignoring whitespace next to block
+    /// elements, which does not render, outside of elements kept verbatim
+    fn dom(html: &str) -> Vec<String> {
+        let document = Html::parse_document(html);
+        let mut nodes = vec![];
+        flatten(document.root_element(), false, &mut nodes);
+
+        nodes
+    }
+
+    fn flatten(element: ElementRef, raw: bool, nodes: &mut Vec<String>) {
+        let mut attrs: Vec<_> = element.value().attrs().collect();
+        attrs.sort();
+        nodes.push(format!("<{} {:?}>", element.value().name(), attrs));
+        let raw = raw || RAW_ELEMENTS.contains(&element.value().name());
+        // Whether the tag on a side of a text is a block element's, skipping comments
+        let is_block = |sibling: Option<&Node>| {
+            let name = match sibling {
+                Some(sibling) => sibling.as_element().map(|e| e.name()),
+                None => Some(element.value().name()),
+            };
+            name.is_some_and(|name| BLOCK_ELEMENTS.contains(&name))
+        };
+        let rendered = |node: &&Node| node.is_element() || node.is_text();
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) if raw => nodes.push(text.to_string()),
+                Node::Text(text) => {
+                    let before = is_block(child.prev_siblings().map(|n| n.value()).find(rendered));
+                    let after = is_block(child.next_siblings().map(|n| n.value()).find(rendered));
+                    let text = collapse_whitespace(text);
+                    let text = if before {
+                        text.trim_start_matches(' ')
+                    } else {
+                        &text
+                    };
+                    let text = if after {
+                        text.trim_end_matches(' ')
+                    } else {
+                        text
+                    };
+                    if !text.is_empty() {
+                        nodes.push(text.to_string());
+                    }
+                }
+                Node::Element(_) => flatten(ElementRef::wrap(child).unwrap(), raw, nodes),
+                _ => {}
+            }
+        }
+        nodes.push(format!("</{}>", element.value().name()));
+    }
+
+    fn assert_equivalent(html: &str) -> String {
+        let minified = minify_html(html);
+
+        assert_eq!(dom(&minified), dom(html));
+        assert!(minified.len() < html.len());
+
+        minified
+    }
+
+    #[test]
+    fn test_minify_article_page() {
+        let site = Site::new(&Config::default(), &[]);
+        let html = article().generate(&site, &Theme::Builtin).unwrap();
+
+        let result = assert_equivalent(&html);
+
+        assert!(!result.contains("\n  <"));
+        assert!(!result.contains("a comment"));
+        assert!(result.contains("    println!(\"  spaced  \");\n"));
+        assert!(result.contains("<script>\n  if (1 < 2 && \"</p>\") {"));
+        assert!(result.contains("<em>inline</em> <strong>element</strong>,&nbsp;and a <a"));
+    }
+
+    #[test]
+    fn test_minify_toppage() {
+        let site = Site::new(&Config::default(), &[]);
+        let toppage = TopPage {
+            section: "diary".to_string(),
+            articles: vec![article(), article()],
+        };
+        let html = toppage.generate(&site, &Theme::Builtin).unwrap();
+
+        assert_equivalent(&html);
+    }
+
+    #[test]
+    fn test_dom_keeps_spaces_between_inline_elements() {
+        assert_ne!(
+            dom("<p><b>a</b> <i>b</i></p>"),
+            dom("<p><b>a</b><i>b</i></p>")
+        );
+        assert_ne!(dom("<p>a <b>b</b></p>"), dom("<p>a<b>b</b></p>"));
+        assert_eq!(dom("<div>\n  <p>a</p>\n</div>"), dom("<div><p>a</p></div>"));
+    }
+
+    #[test]
+    fn test_minify_html() {
+        let html = "<!DOCTYPE html>\n<html>\n  <body>\n    <p>\n      a  <b>b</b>\n      <i>c</i>\n    </p>\n  </body>\n</html>\n";
+
+        let result = minify_html(html);
+
+        assert_eq!(
+            result,
+            "<!DOCTYPE html><html><body><p>a <b>b</b> <i>c</i></p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_minify_keeps_no_break_spaces() {
+        let html = "<p>\u{a0}</p>\n<p> a\u{a0} </p>";
+
+        let result = minify_html(html);
+
+        assert_eq!(result, "<p>\u{a0}</p><p>a\u{a0}</p>");
+    }
+
+    #[test]
+    fn test_minify_keeps_raw_elements() {
+        let html = "<div>\n  <textarea>\n  x\n</textarea>\n  <style>\n a  {}\n</style>\n  <PRE> a\n  b</PRE>\n</div>";
+
+        let result = minify_html(html);
+
+        assert_eq!(
+            result,
+            "<div><textarea>\n  x\n</textarea> <style>\n a  {}\n</style><PRE> a\n  b</PRE></div>"
+        );
+    }
+
+    #[test]
+    fn test_minify_keeps_quoted_brackets() {
+        let html = "<p>\n  <a title=\"a > b\" href='x'>\n    link\n  </a>\n</p>";
+
+        let result = minify_html(html);
+
+        assert_eq!(result, "<p><a title=\"a > b\" href='x'> link </a></p>");
+    }
+}
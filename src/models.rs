@@ -1,40 +1,510 @@
 //! Models for chablo
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
 use std::path::{Path, PathBuf};
 
 use askama::Template;
-use chrono::{Datelike, Local, NaiveDate};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::AssetManifest;
+use crate::config::Config;
 
 pub const BLOG_TITLE: &str = "blog.chansuke.info";
+pub const BASE_URL: &str = "https://blog.chansuke.info";
 pub const DESCRIPTION: &str = "日記、メモ、個人開発、生活の記録";
+pub const AUTHOR: &str = "chansuke";
+/// Number of articles listed in a feed
+pub const FEED_LENGTH: usize = 20;
+/// Number of characters kept when an excerpt is generated from the body
+pub const EXCERPT_LENGTH: usize = 120;
+
+/// Elements which never have a closing tag
+const VOID_ELEMENTS: [&str; 8] = ["br", "hr", "img", "input", "link", "meta", "source", "wbr"];
 
 /// Markdown file path
 #[derive(Debug)]
 pub struct MarkdownPath(pub PathBuf);
 
 /// Converted HTML content from Markdown
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub struct HtmlBody(pub String);
 
+impl HtmlBody {
+    /// Truncate to `max_chars` characters of text, closing the tags left open
+    pub fn truncate(&self, max_chars: usize) -> HtmlBody {
+        let mut output = String::new();
+        let mut open_tags: Vec<&str> = vec![];
+        let mut count = 0;
+        let mut truncated = false;
+        let mut rest = self.0.as_str();
+
+        while let Some(ch) = rest.chars().next() {
+            if ch == '<' {
+                let end = if rest.starts_with("<!--") {
+                    rest.find("-->").map(|i| i + 3)
+                } else {
+                    rest.find('>').map(|i| i + 1)
+                };
+                let Some(end) = end else { break };
+                let tag = &rest[..end];
+                output.push_str(tag);
+                rest = &rest[end..];
+
+                if tag.starts_with("</") {
+                    open_tags.pop();
+                } else if !tag.starts_with("<!") && !tag.ends_with("/>") {
+                    let name = tag[1..tag.len() - 1]
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default();
+                    if !VOID_ELEMENTS.contains(&name) {
+                        open_tags.push(name);
+                    }
+                }
+                continue;
+            }
+
+            // Whitespace between blocks does not count towards the limit
+            if ch.is_whitespace() {
+                output.push(ch);
+                rest = &rest[ch.len_utf8()..];
+                continue;
+            }
+            if count == max_chars {
+                truncated = true;
+                break;
+            }
+
+            // Keep character references such as `&amp;` in one piece
+            let len = match rest.find(';') {
+                Some(i) if ch == '&' && i < 10 => i + 1,
+                _ => ch.len_utf8(),
+            };
+            output.push_str(&rest[..len]);
+            rest = &rest[len..];
+            count += 1;
+        }
+
+        if truncated {
+            output.truncate(output.trim_end().len());
+            output.push('…');
+        }
+        for tag in open_tags.iter().rev() {
+            output.push_str(&format!("</{tag}>"));
+        }
+
+        HtmlBody(output)
+    }
+
+    /// Text content without tags, used for meta descriptions
+    pub fn plain_text(&self) -> String {
+        let mut text = String::new();
+        let mut in_tag = false;
+
+        for ch in self.0.chars() {
+            match ch {
+                '<' => in_tag = true,
+                '>' => in_tag = false,
+                _ if !in_tag => text.push(ch),
+                _ => {}
+            }
+        }
+
+        text.split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&#39;", "'")
+            .replace("&amp;", "&")
+    }
+}
+
 impl std::fmt::Display for HtmlBody {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(fmt, "{}", self.0)
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub struct Article {
     pub id: String,
     pub title: String,
     pub body: HtmlBody,
+    pub excerpt: HtmlBody,
+    pub image: Option<String>,
+    pub tags: Vec<String>,
+    /// Front matter fields which have no dedicated field
+    pub extra: BTreeMap<String, String>,
+    /// Headings of the body, rendered by `article.html` when `toc: true` is set
+    pub toc: Vec<TocEntry>,
+    /// Name of the section the article belongs to
+    pub section: String,
+    /// File name of the source without its extension
+    pub slug: String,
     pub date: NaiveDate,
     pub path: String,
+    /// Markdown file the article was parsed from
+    pub source: PathBuf,
+}
+
+impl Article {
+    /// Whether the table of contents is requested with `toc: true`
+    pub fn show_toc(&self) -> bool {
+        !self.toc.is_empty() && self.extra.get("toc").is_some_and(|toc| toc == "true")
+    }
+
+    /// Table of contents as nested lists, down to the deepest heading
+    pub fn toc_html(&self) -> String {
+        TocEntry::list(&self.toc)
+    }
+}
+
+/// Heading of a body with the headings nested under it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TocEntry {
+    pub level: u8,
+    /// Id of the heading element, linked as `#id`
+    pub id: String,
+    pub title: String,
+    pub children: Vec<TocEntry>,
+}
+
+impl TocEntry {
+    /// Nest a flat list of headings under the preceding headings of a higher level
+    pub fn nest(headings: Vec<TocEntry>) -> Vec<TocEntry> {
+        let mut toc: Vec<TocEntry> = vec![];
+        for heading in headings {
+            Self::insert(&mut toc, heading);
+        }
+
+        toc
+    }
+
+    fn list(entries: &[TocEntry]) -> String {
+        let mut html = String::from("<ol>");
+        for entry in entries {
+            html.push_str(&format!(
+                r##"<li><a href="#{}">{}</a>"##,
+                escape_html(&entry.id),
+                escape_html(&entry.title)
+            ));
+            if !entry.children.is_empty() {
+                html.push_str(&Self::list(&entry.children));
+            }
+            html.push_str("</li>");
+        }
+        html.push_str("</ol>");
+
+        html
+    }
+
+    fn insert(entries: &mut Vec<TocEntry>, heading: TocEntry) {
+        match entries.last_mut() {
+            Some(last) if last.level < heading.level => Self::insert(&mut last.children, heading),
+            _ => entries.push(heading),
+        }
+    }
+}
+
+/// Order of the articles in a section listing
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortOrder {
+    #[default]
+    DateDesc,
+    DateAsc,
+    Title,
+}
+
+/// Content directory with its own listing, templates, feed and URLs
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(from = "SectionConfig")]
+pub struct Section {
+    pub name: String,
+    /// Heading of the listing page, the site title is used when unset
+    pub title: Option<String>,
+    pub dir: String,
+    /// Glob of the markdown files, relative to `dir`
+    pub pattern: String,
+    pub sort: SortOrder,
+    /// Template of each article
+    pub template: String,
+    /// Template of the listing page
+    pub list_template: String,
+    /// Output path of the listing page, relative to `public/`
+    pub list_path: String,
+    /// Output path of each article. `{title}`, `{slug}`, `{year}`, `{month}` and `{day}`
+    /// are replaced with the values of the article.
+    pub permalink: String,
+    /// Output path of the Atom feed, no feed is generated when unset, i.e. with
+    /// `feed = false` in the config
+    pub feed: Option<String>,
+}
+
+impl Default for Section {
+    fn default() -> Self {
+        Section {
+            name: "diary".to_string(),
+            title: None,
+            dir: "diary".to_string(),
+            pattern: "**/*.md".to_string(),
+            sort: SortOrder::DateDesc,
+            template: "article.html".to_string(),
+            list_template: "toppage.html".to_string(),
+            list_path: "index.html".to_string(),
+            permalink: "{title}.html".to_string(),
+            feed: Some("feed.xml".to_string()),
+        }
+    }
+}
+
+/// Section as declared in the config, where the directory and output paths default to
+/// ones under the name of the section, e.g. `notes/index.html` and `notes/feed.xml`
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct SectionConfig {
+    name: String,
+    title: Option<String>,
+    dir: Option<String>,
+    pattern: String,
+    sort: SortOrder,
+    template: String,
+    list_template: String,
+    list_path: Option<String>,
+    permalink: Option<String>,
+    feed: Option<FeedConfig>,
+}
+
+/// `feed` of a section: its output path, or whether it has one at the default path
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FeedConfig {
+    Path(String),
+    Enabled(bool),
+}
+
+impl Default for SectionConfig {
+    fn default() -> Self {
+        let section = Section::default();
+
+        SectionConfig {
+            name: section.name,
+            title: None,
+            dir: None,
+            pattern: section.pattern,
+            sort: section.sort,
+            template: section.template,
+            list_template: section.list_template,
+            list_path: None,
+            permalink: None,
+            feed: None,
+        }
+    }
+}
+
+impl From<SectionConfig> for Section {
+    fn from(config: SectionConfig) -> Self {
+        let name = config.name;
+
+        Section {
+            title: config.title,
+            dir: config.dir.unwrap_or_else(|| name.clone()),
+            pattern: config.pattern,
+            sort: config.sort,
+            template: config.template,
+            list_template: config.list_template,
+            list_path: config
+                .list_path
+                .unwrap_or_else(|| format!("{name}/index.html")),
+            permalink: config
+                .permalink
+                .unwrap_or_else(|| format!("{name}/{{title}}.html")),
+            feed: match config.feed {
+                Some(FeedConfig::Path(path)) => Some(path),
+                Some(FeedConfig::Enabled(false)) => None,
+                None | Some(FeedConfig::Enabled(true)) => Some(format!("{name}/feed.xml")),
+            },
+            name,
+        }
+    }
+}
+
+impl Section {
+    pub fn glob(&self) -> String {
+        format!("{}/{}", self.dir, self.pattern)
+    }
+}
+
+/// Navigation menu entry declared in the config
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Menu {
+    pub name: String,
+    pub url: String,
+    /// Entries are sorted by ascending weight, then by declaration order
+    #[serde(default)]
+    pub weight: i32,
+    #[serde(default)]
+    pub children: Vec<Menu>,
+    /// Whether the entry or one of its children points to the current page
+    #[serde(default, skip_deserializing)]
+    pub active: bool,
+    #[serde(default, skip_deserializing)]
+    pub external: bool,
+}
+
+impl Menu {
+    fn sorted(menus: &[Menu]) -> Vec<Menu> {
+        let mut menus: Vec<Menu> = menus
+            .iter()
+            .map(|menu| Menu {
+                children: Menu::sorted(&menu.children),
+                external: menu.url.starts_with("http://") || menu.url.starts_with("https://"),
+                ..menu.clone()
+            })
+            .collect();
+        menus.sort_by_key(|menu| menu.weight);
+
+        menus
+    }
+
+    fn activated(menus: &[Menu], path: &str) -> Vec<Menu> {
+        menus
+            .iter()
+            .map(|menu| {
+                let children = Menu::activated(&menu.children, path);
+                let active =
+                    normalize_path(&menu.url) == path || children.iter().any(|child| child.active);
+
+                Menu {
+                    children,
+                    active,
+                    ..menu.clone()
+                }
+            })
+            .collect()
+    }
+}
+
+/// Site-wide data available to every template
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Site {
+    pub title: String,
+    pub description: String,
+    pub base_url: String,
+    pub author: String,
+    pub menus: Vec<Menu>,
+    pub sections: Vec<Section>,
+    /// Tags of all articles, sorted and deduplicated
+    pub tags: Vec<String>,
+    pub build_time: NaiveDateTime,
+    /// Fingerprinted copies of stylesheets and scripts
+    pub assets: AssetManifest,
+}
+
+impl Site {
+    pub fn new(config: &Config, articles: &[Article]) -> Self {
+        let tags: BTreeSet<&String> = articles.iter().flat_map(|a| &a.tags).collect();
+
+        Site {
+            title: config.title.clone(),
+            description: config.description.clone(),
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            author: config.author.clone(),
+            menus: Menu::sorted(&config.menus),
+            sections: config.sections.clone(),
+            tags: tags.into_iter().cloned().collect(),
+            build_time: Local::now().naive_local(),
+            assets: AssetManifest::default(),
+        }
+    }
+
+    /// Section called `name`, or the default one when it is not configured
+    pub fn section(&self, name: &str) -> Section {
+        self.sections
+            .iter()
+            .find(|section| section.name == name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Menus with the entries leading to `path` marked as active
+    pub fn menus_for(&self, path: &str) -> Vec<Menu> {
+        Menu::activated(&self.menus, &normalize_path(path))
+    }
+
+    /// Resolve a site-relative path into an absolute, percent-encoded URL
+    pub fn absolute_url(&self, path: &str) -> String {
+        if path.starts_with("http://") || path.starts_with("https://") {
+            return path.to_string();
+        }
+        let path = path.trim_start_matches("./").trim_start_matches('/');
+
+        format!("{}/{}", self.base_url, percent_encode(path))
+    }
+
+    /// URL of an asset under `public/`, pointing to its fingerprinted copy if any
+    pub fn asset_url(&self, path: &str) -> String {
+        self.assets.asset_url(path)
+    }
+}
+
+/// Page metadata for canonical links, Open Graph and Twitter Cards
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PageMeta {
+    pub url: String,
+    pub og_type: &'static str,
+    pub image: Option<String>,
+    pub published_time: Option<NaiveDate>,
+}
+
+/// Standalone page outside the diary timeline, such as About or Contact
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Page {
+    pub title: String,
+    pub body: HtmlBody,
+    pub excerpt: HtmlBody,
+    pub image: Option<String>,
+    /// Front matter fields which have no dedicated field
+    pub extra: BTreeMap<String, String>,
+    /// Output path relative to `public/`, mirroring the location under `pages/`
+    pub path: String,
 }
 
+/// Key-value pairs declared between the leading `---` fences
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrontMatter(pub BTreeMap<String, String>);
+
+impl FrontMatter {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|value| value.as_str())
+    }
+}
+
+/// Listing page of a section
 pub struct TopPage {
+    pub section: String,
+    pub articles: Vec<Article>,
+}
+
+/// Atom feed of a section
+pub struct Feed {
+    pub section: String,
     pub articles: Vec<Article>,
 }
 
+/// Entry of an Atom feed with its URL resolved
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FeedEntry {
+    pub title: String,
+    pub url: String,
+    pub date: NaiveDate,
+    pub summary: HtmlBody,
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct UpdatedTitle(pub String);
 
@@ -47,14 +517,55 @@ pub fn curent_datetime() -> NaiveDate {
     NaiveDate::from_ymd_opt(year, month, day).unwrap_or_default()
 }
 
-pub fn created_datetime(path: &Path) -> Option<NaiveDate> {
-    let path_str = path.to_string_lossy();
+/// Day the file was last modified, in the local time zone
+pub fn modified_datetime(path: &Path) -> Option<NaiveDate> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+
+    Some(DateTime::<Local>::from(modified).date_naive())
+}
+
+/// Date in the path of an article relative to the directory of its section, e.g.
+/// `2023/01/07.md`. Digits of the directory itself are never read as the date.
+pub fn created_datetime(path: &Path, dir: &Path) -> Option<NaiveDate> {
+    let path_str = path.strip_prefix(dir).unwrap_or(path).to_string_lossy();
+
+    // Convert extracted time, paths without a full valid date have none
+    extract_time(&path_str)
+        .filter(|date| date.len() >= 3)
+        .and_then(|date| NaiveDate::from_ymd_opt(date[0], date[1] as u32, date[2] as u32))
+}
+
+/// Normalize a site-relative URL so that `/`, `./index.html` and `index.html` compare equal
+fn normalize_path(path: &str) -> String {
+    let path = path.trim_start_matches("./").trim_start_matches('/');
 
-    // Convert extracted time
-    extract_time(&path_str).map(|date| {
-        NaiveDate::from_ymd_opt(date[0], date[1] as u32, date[2] as u32)
-            .unwrap_or_else(curent_datetime)
-    })
+    match path {
+        "" => "index.html".to_string(),
+        _ => path.to_string(),
+    }
+}
+
+/// Escape text for HTML content and attribute values
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::new();
+    pulldown_cmark_escape::escape_html(&mut escaped, text).expect("writing to a string");
+
+    escaped
+}
+
+pub(crate) fn percent_encode(path: &str) -> String {
+    let mut encoded = String::new();
+
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    encoded
 }
 
 fn extract_time(path_str: &str) -> Option<Vec<i32>> {
@@ -69,26 +580,57 @@ fn extract_time(path_str: &str) -> Option<Vec<i32>> {
     Some(year_month_day)
 }
 
-#[derive(Template)]
+#[derive(Template, Serialize)]
 #[template(path = "article.html")]
 pub(crate) struct ArticleTemplate<'a> {
     pub title: &'a str,
-    pub body: HtmlBody,
+    pub body: &'a HtmlBody,
     pub date: NaiveDate,
     pub description: &'a str,
+    pub meta: PageMeta,
+    pub menus: Vec<Menu>,
+    pub site: &'a Site,
+    pub page: &'a Article,
 }
 
-#[derive(Template)]
+#[derive(Template, Serialize)]
+#[template(path = "page.html")]
+pub(crate) struct PageTemplate<'a> {
+    pub title: &'a str,
+    pub body: &'a HtmlBody,
+    pub description: &'a str,
+    pub meta: PageMeta,
+    pub menus: Vec<Menu>,
+    pub site: &'a Site,
+    pub page: &'a Page,
+}
+
+#[derive(Template, Serialize)]
+#[template(path = "feed.xml")]
+pub(crate) struct FeedTemplate<'a> {
+    pub title: &'a str,
+    /// Absolute URL of the feed itself
+    pub url: String,
+    pub updated: NaiveDate,
+    pub entries: Vec<FeedEntry>,
+    pub site: &'a Site,
+}
+
+#[derive(Template, Serialize)]
 #[template(path = "toppage.html")]
 pub(crate) struct TopPageTemplate<'a> {
     pub title: &'a str,
     pub articles: Vec<Article>,
     pub description: &'a str,
+    pub meta: PageMeta,
+    pub menus: Vec<Menu>,
+    pub site: &'a Site,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::article;
 
     #[test]
     fn test_parse_year() {
@@ -99,13 +641,158 @@ mod tests {
         assert_eq!(result, expected_result);
     }
 
+    #[test]
+    fn test_created_datetime_without_date() {
+        let path = PathBuf::from("notes/rust.md");
+        let result = created_datetime(&path, Path::new("notes"));
+
+        assert_eq!(result, None);
+    }
+
     #[test]
     fn test_created_datetime() {
         let before = "tests/fixtures/2050/05/30.md";
         let path = PathBuf::from(before);
-        let result = created_datetime(&path).unwrap();
+        let result = created_datetime(&path, Path::new("tests/fixtures")).unwrap();
         let expected_result = NaiveDate::from_ymd_opt(2050, 0o5, 30).unwrap();
 
         assert_eq!(result, expected_result);
     }
+
+    #[test]
+    fn test_created_datetime_ignores_section_dir() {
+        let dir = Path::new("/tmp/chablo_2x9/2024");
+        let dated = created_datetime(&dir.join("2023/04/07/index.md"), dir);
+        let undated = created_datetime(&dir.join("notes/rust.md"), dir);
+        let invalid = created_datetime(&dir.join("2023/13/07.md"), dir);
+
+        assert_eq!(dated, NaiveDate::from_ymd_opt(2023, 4, 7));
+        assert_eq!(undated, None);
+        assert_eq!(invalid, None);
+    }
+
+    #[test]
+    fn test_absolute_url() {
+        let site = Site::new(&Config::default(), &[]);
+
+        assert_eq!(
+            site.absolute_url("./css/main.css"),
+            "https://blog.chansuke.info/css/main.css"
+        );
+        assert_eq!(
+            site.absolute_url("年末年始.html"),
+            "https://blog.chansuke.info/%E5%B9%B4%E6%9C%AB%E5%B9%B4%E5%A7%8B.html"
+        );
+        assert_eq!(
+            site.absolute_url("https://example.com/a.png"),
+            "https://example.com/a.png"
+        );
+    }
+
+    #[test]
+    fn test_menus_for() {
+        let menu = |name: &str, url: &str, weight: i32, children: Vec<Menu>| Menu {
+            name: name.to_string(),
+            url: url.to_string(),
+            weight,
+            children,
+            active: false,
+            external: false,
+        };
+        let config = Config {
+            menus: vec![
+                menu("Feed", "/feed.xml", 20, vec![]),
+                menu(
+                    "About",
+                    "/about.html",
+                    10,
+                    vec![menu("GitHub", "https://github.com/chansuke", 0, vec![])],
+                ),
+                menu("Home", "/", 0, vec![]),
+            ],
+            ..Config::default()
+        };
+        let site = Site::new(&config, &[]);
+
+        let result = site.menus_for("index.html");
+        let names: Vec<&str> = result.iter().map(|menu| menu.name.as_str()).collect();
+
+        assert_eq!(names, vec!["Home", "About", "Feed"]);
+        assert!(result[0].active);
+        assert!(!result[1].active);
+        assert!(result[1].children[0].external);
+
+        let result = site.menus_for("about.html");
+
+        assert!(!result[0].active);
+        assert!(result[1].active);
+    }
+
+    #[test]
+    fn test_site_collects_tags() {
+        let article = |tags: &[&str]| Article {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..article("title")
+        };
+        let articles = vec![article(&["rust", "日記"]), article(&["book", "rust"])];
+
+        let result = Site::new(&Config::default(), &articles);
+
+        assert_eq!(result.tags, vec!["book", "rust", "日記"]);
+    }
+
+    #[test]
+    fn test_truncate_closes_open_tags() {
+        let html = HtmlBody("<p>Hello <strong>world</strong></p>\n".to_string());
+        let result = html.truncate(7);
+
+        assert_eq!(result.0, "<p>Hello <strong>wo…</strong></p>");
+    }
+
+    #[test]
+    fn test_truncate_with_ja() {
+        let html = HtmlBody("<p>昨日、今日にかけて<br>ブログを作った</p>".to_string());
+        let result = html.truncate(11);
+
+        assert_eq!(result.0, "<p>昨日、今日にかけて<br>ブロ…</p>");
+    }
+
+    #[test]
+    fn test_truncate_keeps_short_body() {
+        let html = HtmlBody("<p>A &amp; B</p>\n".to_string());
+        let result = html.truncate(5);
+
+        assert_eq!(result, html);
+    }
+
+    #[test]
+    fn test_plain_text() {
+        let html = HtmlBody("<p>A &amp; <em>B</em></p>\n<p>C</p>\n".to_string());
+        let result = html.plain_text();
+
+        assert_eq!(result, "A & B C");
+    }
+
+    #[test]
+    fn test_toc_entry_nest() {
+        let heading = |level: u8, id: &str| TocEntry {
+            level,
+            id: id.to_string(),
+            title: id.to_string(),
+            children: vec![],
+        };
+        let headings = vec![
+            heading(2, "a"),
+            heading(3, "a-1"),
+            heading(4, "a-1-1"),
+            heading(2, "b"),
+        ];
+
+        let result = TocEntry::nest(headings);
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].children[0].id, "a-1");
+        assert_eq!(result[0].children[0].children[0].id, "a-1-1");
+        assert!(result[1].children.is_empty());
+    }
 }
@@ -0,0 +1,259 @@
+//! Generate Open Graph images for articles
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use unifont::Glyph;
+
+use crate::errors::ChabloError;
+use crate::models::{Article, Site};
+use crate::writer::Outputs;
+
+/// Directory of the generated images, relative to the output directory
+pub const OG_IMAGE_DIR: &str = "og";
+
+const WIDTH: usize = 1200;
+const HEIGHT: usize = 630;
+const MARGIN: usize = 80;
+const TITLE_SCALE: usize = 4;
+const TITLE_MAX_LINES: usize = 4;
+const FOOTER_SCALE: usize = 2;
+const BACKGROUND: [u8; 3] = [0x1e, 0x22, 0x28];
+const FOREGROUND: [u8; 3] = [0xf4, 0xf4, 0xf0];
+const ACCENT: [u8; 3] = [0xe0, 0x8a, 0x3c];
+
+/// Bump when the layout changes so that cached images are regenerated
+const LAYOUT_VERSION: &str = "1";
+
+/// Render the card of an article into `<output_dir>/og/` and return its path relative to
+/// `output_dir`. Images are named by the hash of their content, so an existing file is reused.
+pub fn generate_og_image(
+    article: &Article,
+    site: &Site,
+    output_dir: &str,
+    outputs: &mut Outputs,
+) -> Result<String, ChabloError> {
+    let date = article.date.to_string();
+    let path = format!(
+        "{}/{}.png",
+        OG_IMAGE_DIR,
+        content_hash(&[LAYOUT_VERSION, &site.title, &article.title, &date])
+    );
+    let output_path = format!("{}/{}", output_dir, path);
+
+    if Path::new(&output_path).exists() {
+        outputs.mark(Path::new(&output_path));
+        return Ok(path);
+    }
+
+    let mut canvas = Canvas::new(BACKGROUND);
+    canvas.fill_rect(0, 0, WIDTH, 16, ACCENT);
+
+    let line_height = 20 * TITLE_SCALE;
+    let title_width = WIDTH - MARGIN * 2;
+    for (i, line) in wrap_text(&article.title, title_width, TITLE_SCALE)
+        .iter()
+        .enumerate()
+    {
+        canvas.draw_text(
+            line,
+            MARGIN,
+            MARGIN + i * line_height,
+            TITLE_SCALE,
+            FOREGROUND,
+        );
+    }
+
+    let footer_y = HEIGHT - MARGIN - 16 * FOOTER_SCALE;
+    canvas.draw_text(&date, MARGIN, footer_y, FOOTER_SCALE, ACCENT);
+    let site_x = WIDTH - MARGIN - text_width(&site.title, FOOTER_SCALE);
+    canvas.draw_text(&site.title, site_x, footer_y, FOOTER_SCALE, FOREGROUND);
+
+    outputs.write(canvas.encode()?, &output_path)?;
+
+    Ok(path)
+}
+
+/// Hex digest of the given parts, shortened for use in file names
+pub(crate) fn content_hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+
+    hasher.finalize()[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Unifont glyph of a character, falling back to `?` for unsupported ones
+fn glyph(ch: char) -> &'static Glyph {
+    unifont::get_glyph(ch)
+        .or_else(|| unifont::get_glyph('?'))
+        .expect("Unifont covers ASCII")
+}
+
+fn text_width(text: &str, scale: usize) -> usize {
+    text.chars().map(|ch| glyph(ch).get_width() * scale).sum()
+}
+
+/// Wrap text into lines fitting `max_width`. Latin words are kept together where possible,
+/// CJK text is broken between any characters.
+fn wrap_text(text: &str, max_width: usize, scale: usize) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+
+    for ch in text.chars() {
+        if line.is_empty() && ch == ' ' {
+            continue;
+        }
+        line.push(ch);
+        if text_width(&line, scale) <= max_width {
+            continue;
+        }
+
+        line.pop();
+        let next = match line.rfind(' ') {
+            Some(i) if !ch.is_whitespace() && line[i..].is_ascii() => {
+                let next = line[i + 1..].to_string();
+                line.truncate(i);
+                next
+            }
+            _ => String::new(),
+        };
+        lines.push(line);
+        line = next;
+        if !ch.is_whitespace() {
+            line.push(ch);
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    if lines.len() > TITLE_MAX_LINES {
+        lines.truncate(TITLE_MAX_LINES);
+        let last = &mut lines[TITLE_MAX_LINES - 1];
+        while text_width(&format!("{last}…"), scale) > max_width {
+            last.pop();
+        }
+        last.push('…');
+    }
+
+    lines
+}
+
+/// RGB pixel buffer of the card
+struct Canvas {
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(color: [u8; 3]) -> Self {
+        Canvas {
+            pixels: color.repeat(WIDTH * HEIGHT),
+        }
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        for py in y..(y + height).min(HEIGHT) {
+            for px in x..(x + width).min(WIDTH) {
+                let offset = (py * WIDTH + px) * 3;
+                self.pixels[offset..offset + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Draw text with each glyph pixel scaled up to a `scale`×`scale` square
+    fn draw_text(&mut self, text: &str, x: usize, y: usize, scale: usize, color: [u8; 3]) {
+        let mut cursor = x;
+
+        for ch in text.chars() {
+            let glyph = glyph(ch);
+            for gy in 0..16 {
+                for gx in 0..glyph.get_width() {
+                    if glyph.get_pixel(gx, gy) {
+                        let px = cursor + gx * scale;
+                        self.fill_rect(px, y + gy * scale, scale, scale, color);
+                    }
+                }
+            }
+            cursor += glyph.get_width() * scale;
+        }
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, ChabloError> {
+        let mut buffer = vec![];
+        let mut encoder = png::Encoder::new(&mut buffer, WIDTH as u32, HEIGHT as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_utils::{article, temp_dir};
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    #[test]
+    fn test_generate_og_image_ok() {
+        let dir = temp_dir("og_image");
+        let output_dir = &*dir.path().to_string_lossy();
+        let article = article("ブログシステムを作っていた");
+
+        let site = Site::new(&Config::default(), &[]);
+
+        let path = generate_og_image(&article, &site, output_dir, &mut Outputs::default()).unwrap();
+        let mut outputs = Outputs::default();
+        let cached = generate_og_image(&article, &site, output_dir, &mut outputs).unwrap();
+        let data = fs::read(format!("{}/{}", output_dir, path)).unwrap();
+
+        assert_eq!(path, cached);
+        // A reused image is still an output of the build
+        assert!(outputs.contains(Path::new(&format!("{}/{}", output_dir, path))));
+        assert!(path.starts_with("og/") && path.ends_with(".png"));
+        assert_eq!(&data[1..4], b"PNG");
+    }
+
+    #[test]
+    fn test_content_hash_is_stable() {
+        let first = content_hash(&["a", "bc"]);
+        let second = content_hash(&["ab", "c"]);
+
+        assert_eq!(first, content_hash(&["a", "bc"]));
+        assert_eq!(first.len(), 16);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_wrap_text_with_ja() {
+        let result = wrap_text("年末年始の振り返り", 16 * 5, 1);
+
+        assert_eq!(result, vec!["年末年始の", "振り返り"]);
+    }
+
+    #[test]
+    fn test_wrap_text_keeps_words() {
+        let result = wrap_text("hello big world", 8 * 10, 1);
+
+        assert_eq!(result, vec!["hello big", "world"]);
+    }
+
+    #[test]
+    fn test_wrap_text_with_too_many_lines() {
+        let result = wrap_text(&"あ".repeat(30), 16 * 5, 1);
+
+        assert_eq!(result.len(), TITLE_MAX_LINES);
+        assert_eq!(result[TITLE_MAX_LINES - 1], "ああああ…");
+    }
+}
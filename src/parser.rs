@@ -1,81 +1,449 @@
 //! Parse html to markdown
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 use anyhow::Result;
-use pulldown_cmark::{html, Options, Parser};
+use chrono::NaiveDate;
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use regex::Regex;
 
+use crate::bundle::is_bundle;
+use crate::config::MarkdownOptions;
+use crate::diagram::{is_diagram, Diagrams};
 use crate::errors::ChabloError;
-use crate::models::{created_datetime, Article, HtmlBody};
+use crate::highlight::{highlight, CodeInfo};
+use crate::math::render_math;
+use crate::models::{
+    created_datetime, curent_datetime, escape_html, modified_datetime, Article, FrontMatter,
+    HtmlBody, Page, TocEntry, EXCERPT_LENGTH,
+};
+use crate::shortcode::Shortcodes;
 
-pub fn parse(path: PathBuf) -> Result<Article, ChabloError> {
+/// Marker separating the excerpt from the rest of the body
+const MORE_MARKER: &str = "<!-- more -->";
+
+/// Front matter fields mapped to dedicated fields of `Article`
+const KNOWN_FIELDS: [&str; 5] = ["title", "description", "image", "tags", "date"];
+
+/// Parse an article of the section stored in `dir`
+pub fn parse(
+    path: PathBuf,
+    dir: &Path,
+    markdown: &MarkdownOptions,
+    shortcodes: &Shortcodes,
+    diagrams: &Diagrams,
+) -> Result<Article, ChabloError> {
     // Extract the content of a markdown file
     let content = fs::read_to_string(&path)?;
-    let created_time = created_datetime(&path).unwrap();
     let title = extract_title(&content)?;
+    let front_matter = extract_front_matter(&content);
+    // The `date` field wins over the date in the path, e.g. `diary/2023/01/07.md`, and
+    // undated files keep the day they were last modified from one build to the next
+    let created_time = front_matter
+        .get("date")
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+        .or_else(|| created_datetime(&path, dir))
+        .or_else(|| modified_datetime(&path))
+        .unwrap_or_else(curent_datetime);
+    let options = markdown.with_front_matter(&front_matter).to_options();
     let body = extract_body(&content)?;
-    let html_body = convert_md_to_html(body)?;
+    let first_line = body_line(&content, body);
+    let (body, lines) = shortcodes
+        .expand(body)
+        .map_err(|error| error.in_file(&path, first_line))?;
+    // Errors of the expanded body are reported at the lines of the source
+    let in_file = |error: ChabloError| lines.locate(error).in_file(&path, first_line);
+    let (html_body, headings) = render_markdown(&body, options, diagrams).map_err(in_file)?;
+    let excerpt =
+        extract_excerpt(&front_matter, &body, &html_body, options, diagrams).map_err(in_file)?;
+    let image = extract_image(&front_matter, &html_body);
+    let tags = extract_tags(&front_matter);
+    let extra = extract_extra(front_matter);
+    // A bundle `diary/2023/04/07/index.md` is named after its directory
+    let slug_path = match is_bundle(&path) {
+        true => path.parent().unwrap_or(&path),
+        false => &path,
+    };
+    let slug = slug_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
     let id = format!("{}_{}", &created_time, title);
+    let source = path;
     let path = format!("{}{}", &title, ".html");
 
     let article = Article {
         id,
         title: title.to_string(),
         body: html_body,
+        excerpt,
+        image,
+        tags,
+        extra,
+        toc: TocEntry::nest(headings),
+        section: "diary".to_string(),
+        slug,
         date: created_time,
         path,
+        source,
     };
 
     Ok(article)
 }
 
+/// Parse a standalone page, `root` being the directory the output path is relative to
+pub fn parse_page(
+    path: &Path,
+    root: &Path,
+    markdown: &MarkdownOptions,
+    shortcodes: &Shortcodes,
+    diagrams: &Diagrams,
+) -> Result<Page, ChabloError> {
+    let content = fs::read_to_string(path)?;
+    let front_matter = extract_front_matter(&content);
+    let options = markdown.with_front_matter(&front_matter).to_options();
+    let body = extract_body(&content)?;
+    let first_line = body_line(&content, body);
+    let (body, lines) = shortcodes
+        .expand(body)
+        .map_err(|error| error.in_file(path, first_line))?;
+    let in_file = |error: ChabloError| lines.locate(error).in_file(path, first_line);
+    let html_body = convert_md_to_html(&body, options, diagrams).map_err(in_file)?;
+    let excerpt =
+        extract_excerpt(&front_matter, &body, &html_body, options, diagrams).map_err(in_file)?;
+    let image = extract_image(&front_matter, &html_body);
+    let relative_path = path.strip_prefix(root).unwrap_or(path);
+    let title = match front_matter.get("title") {
+        Some(title) if !title.is_empty() => title.to_string(),
+        _ => relative_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+    };
+    let path = page_path(path, root);
+
+    let page = Page {
+        title,
+        body: html_body,
+        excerpt,
+        image,
+        extra: extract_extra(front_matter),
+        path,
+    };
+
+    Ok(page)
+}
+
+/// Output path of a page, relative to `public/` as the source is to `root`
+pub fn page_path(path: &Path, root: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .with_extension("html")
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
 /// Convert markdown to html body
-fn convert_md_to_html(md: &str) -> Result<HtmlBody, ChabloError> {
-    // Set up options and parser. Strikethroughs are not part of the CommonMark standard
-    // and we therefore must enable it explicitly.
-    let mut options = Options::empty();
-    options.insert(Options::ENABLE_STRIKETHROUGH);
-    let parser = Parser::new_ext(md, options);
+fn convert_md_to_html(
+    md: &str,
+    options: Options,
+    diagrams: &Diagrams,
+) -> Result<HtmlBody, ChabloError> {
+    render_markdown(md, options, diagrams).map(|(html_body, _)| html_body)
+}
+
+/// Convert markdown to html body, returning the headings in document order
+fn render_markdown(
+    md: &str,
+    options: Options,
+    diagrams: &Diagrams,
+) -> Result<(HtmlBody, Vec<TocEntry>), ChabloError> {
+    // Extensions such as strikethroughs are not part of the CommonMark standard
+    // and are enabled through the options.
+    let parser = Parser::new_ext(md, options).into_offset_iter();
+
+    // Replace code blocks with their highlighted HTML, render math and give headings unique ids
+    let mut events: Vec<Event> = vec![];
+    let mut code_block: Option<(CodeInfo, String)> = None;
+    let mut heading: Option<(usize, String)> = None;
+    let mut headings: Vec<TocEntry> = vec![];
+    for (event, range) in parser {
+        match event {
+            Event::InlineMath(ref tex) | Event::DisplayMath(ref tex) => {
+                let display = matches!(event, Event::DisplayMath(_));
+                let mathml =
+                    render_math(tex, display).map_err(|error| ChabloError::SourceError {
+                        path: String::new(),
+                        line: md[..range.start].matches('\n').count() + 1,
+                        source: Box::new(error),
+                    })?;
+                events.push(Event::InlineHtml(mathml.into()));
+            }
+            Event::Start(Tag::Heading { .. }) => {
+                heading = Some((events.len(), String::new()));
+                events.push(event);
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                let Some((start, title)) = heading.take() else {
+                    continue;
+                };
+                let Event::Start(Tag::Heading {
+                    id, classes, attrs, ..
+                }) = events[start].clone()
+                else {
+                    continue;
+                };
+                let id = unique_id(
+                    id.map_or_else(|| slugify(&title), |id| id.to_string()),
+                    &headings,
+                );
+                events[start] = Event::Start(Tag::Heading {
+                    level,
+                    id: Some(CowStr::from(id.clone())),
+                    classes,
+                    attrs,
+                });
+                events.push(Event::Html(
+                    format!(
+                        r##"<a class="anchor" href="#{}" aria-label="#"></a>"##,
+                        escape_html(&id)
+                    )
+                    .into(),
+                ));
+                events.push(event);
+                headings.push(TocEntry {
+                    level: level as u8,
+                    id,
+                    title: title.trim().to_string(),
+                    children: vec![],
+                });
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let info = match kind {
+                    CodeBlockKind::Fenced(info) => CodeInfo::parse(&info),
+                    CodeBlockKind::Indented => CodeInfo::default(),
+                };
+                code_block = Some((info, String::new()));
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((info, code)) = code_block.take() {
+                    let diagram = is_diagram(&info.lang)
+                        .then(|| diagrams.render(&info.lang, &code))
+                        .flatten();
+                    let html = match diagram {
+                        Some(svg) => format!("<figure class=\"diagram\">{svg}</figure>\n"),
+                        None => highlight(&code, &info)?,
+                    };
+                    events.push(Event::Html(html.into()));
+                }
+            }
+            Event::Text(text) => match code_block.as_mut() {
+                Some((_, code)) => code.push_str(&text),
+                None => {
+                    if let Some((_, title)) = heading.as_mut() {
+                        title.push_str(&text);
+                    }
+                    events.push(Event::Text(text));
+                }
+            },
+            Event::Code(ref code) => {
+                if let Some((_, title)) = heading.as_mut() {
+                    title.push_str(code);
+                }
+                events.push(event);
+            }
+            _ => events.push(event),
+        }
+    }
 
     // Write to String buffer.
     let mut html_output = String::new();
-    html::push_html(&mut html_output, parser);
+    html::push_html(&mut html_output, events.into_iter());
     let content = HtmlBody(html_output);
 
-    Ok(content)
+    Ok((content, headings))
 }
 
-/// Extract title from raw-content
-fn extract_title(content: &str) -> Result<String, ChabloError> {
-    let parts: Vec<&str> = content.split("---").collect();
-
-    if parts.len() < 2 {
-        return Ok("タイトル無し".to_string());
+/// Line of `content` at which `body` starts, for reporting errors in the body
+fn body_line(content: &str, body: &str) -> usize {
+    match content.strip_suffix(body) {
+        Some(head) => head.matches('\n').count() + 1,
+        None => 1,
     }
-    if let Some(title_part) = parts[1].split("title:").nth(1) {
-        let title = title_part.trim().replace('/', "-");
-        Ok(title)
+}
+
+/// Make an id from heading text, keeping letters of any script such as Japanese
+fn slugify(text: &str) -> String {
+    let slug = text
+        .to_lowercase()
+        .split(|ch: char| !(ch.is_alphanumeric() || ch == '_'))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+
+    if slug.is_empty() {
+        "section".to_string()
     } else {
-        Err(ChabloError::ExtractTitleError)
+        slug
+    }
+}
+
+/// Suffix `-1`, `-2`, ... to ids already taken by earlier headings
+fn unique_id(id: String, headings: &[TocEntry]) -> String {
+    let taken = |id: &str| headings.iter().any(|heading| heading.id == id);
+    if !taken(&id) {
+        return id;
     }
+
+    (1..)
+        .map(|n| format!("{id}-{n}"))
+        .find(|candidate| !taken(candidate))
+        .unwrap_or(id)
+}
+
+/// Build the excerpt from the `description` field, the more marker or the body
+fn extract_excerpt(
+    front_matter: &FrontMatter,
+    body: &str,
+    html_body: &HtmlBody,
+    options: Options,
+    diagrams: &Diagrams,
+) -> Result<HtmlBody, ChabloError> {
+    let excerpt = match (
+        front_matter.get("description"),
+        body.split_once(MORE_MARKER),
+    ) {
+        (Some(description), _) => {
+            convert_md_to_html(description, options, diagrams)?.truncate(EXCERPT_LENGTH)
+        }
+        (None, Some((summary, _))) => convert_md_to_html(summary, options, diagrams)?,
+        (None, None) => html_body.truncate(EXCERPT_LENGTH),
+    };
+
+    Ok(strip_heading_anchors(&excerpt))
+}
+
+/// Remove the ids and anchors of headings, which would be duplicated on pages listing
+/// several excerpts
+fn strip_heading_anchors(html: &HtmlBody) -> HtmlBody {
+    static ANCHOR: OnceLock<Regex> = OnceLock::new();
+    static HEADING_ID: OnceLock<Regex> = OnceLock::new();
+    let anchor = ANCHOR.get_or_init(|| {
+        Regex::new(r##"<a class="anchor" href="#[^"]*" aria-label="#"></a>"##).expect("valid regex")
+    });
+    let heading_id =
+        HEADING_ID.get_or_init(|| Regex::new(r#"<(h[1-6]) id="[^"]*""#).expect("valid regex"));
+
+    let html = anchor.replace_all(&html.0, "");
+
+    HtmlBody(heading_id.replace_all(&html, "<$1").to_string())
+}
+
+/// Pick the `image` field or the first image of the body for social previews
+fn extract_image(front_matter: &FrontMatter, html_body: &HtmlBody) -> Option<String> {
+    if let Some(image) = front_matter.get("image") {
+        return Some(image.to_string());
+    }
+
+    let (_, after) = html_body.0.split_once("<img src=\"")?;
+    after.split_once('"').map(|(src, _)| src.to_string())
+}
+
+/// Keep the front matter fields which have no dedicated field
+fn extract_extra(front_matter: FrontMatter) -> BTreeMap<String, String> {
+    front_matter
+        .0
+        .into_iter()
+        .filter(|(key, _)| !KNOWN_FIELDS.contains(&key.as_str()) && !key.starts_with("markdown."))
+        .collect()
+}
+
+/// Split `tags: [a, b]` or `tags: a, b` into a list
+fn extract_tags(front_matter: &FrontMatter) -> Vec<String> {
+    let Some(tags) = front_matter.get("tags") else {
+        return vec![];
+    };
+
+    tags.trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|tag| tag.trim().trim_matches('"').to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Split raw-content into the front matter between a leading `---` line and the next
+/// `---` line, and the body after it. Other `---`, such as in table delimiter rows or
+/// thematic breaks, belong to the body.
+fn split_front_matter(content: &str) -> Option<(&str, &str)> {
+    let mut lines = content.split_inclusive('\n');
+    let opening = lines.next()?;
+    if opening.trim_end() != "---" {
+        return None;
+    }
+
+    let mut offset = opening.len();
+    for line in lines {
+        if line.trim_end() == "---" {
+            return Some((
+                &content[opening.len()..offset],
+                &content[offset + line.len()..],
+            ));
+        }
+        offset += line.len();
+    }
+
+    None
+}
+
+/// Extract front matter fields from raw-content
+fn extract_front_matter(content: &str) -> FrontMatter {
+    let mut front_matter = FrontMatter::default();
+    let Some((fields, _)) = split_front_matter(content) else {
+        return front_matter;
+    };
+
+    for line in fields.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim().trim_matches('"');
+            front_matter
+                .0
+                .insert(key.trim().to_string(), value.to_string());
+        }
+    }
+
+    front_matter
 }
 
 /// Extract title from raw-content
+fn extract_title(content: &str) -> Result<String, ChabloError> {
+    let Some((fields, _)) = split_front_matter(content) else {
+        return Ok("タイトル無し".to_string());
+    };
+
+    fields
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == "title").then(|| value.trim().replace('/', "-"))
+        })
+        .ok_or(ChabloError::ExtractTitleError)
+}
+
+/// Extract body from raw-content, following the front matter if any
 fn extract_body(content: &str) -> Result<&str, ChabloError> {
-    let v: Vec<&str> = content.split("---").collect();
+    let body = match split_front_matter(content) {
+        Some((_, body)) => body,
+        None => content,
+    };
 
-    match v.len() {
-        0 => Ok("本文はありません。"),
-        1 => match v[0].is_empty() {
-            true => Ok("本文はありません。"),
-            false => Ok(v[0]),
-        },
-        2 => Ok(v[1]),
-        3 => match v[2].is_empty() {
-            true => Ok("本文はありません。"),
-            false => Ok(v[2]),
-        },
-        _ => Err(ChabloError::MatchError),
+    match body.is_empty() {
+        true => Ok("本文はありません。"),
+        false => Ok(body),
     }
 }
 
@@ -83,42 +451,201 @@ fn extract_body(content: &str) -> Result<&str, ChabloError> {
 mod tests {
     use super::*;
     use crate::models::HtmlBody;
+    use crate::test_utils::{article, temp_dir};
+    use chrono::{Local, TimeZone};
     use pretty_assertions::assert_eq;
+    use std::ops::Deref;
     use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// Diagrams cached in a temporary directory, not in the repository, removed with them
+    struct TempDiagrams {
+        diagrams: Diagrams,
+        _dir: TempDir,
+    }
+
+    impl Deref for TempDiagrams {
+        type Target = Diagrams;
+
+        fn deref(&self) -> &Diagrams {
+            &self.diagrams
+        }
+    }
+
+    fn diagrams() -> TempDiagrams {
+        let dir = temp_dir("parser_diagrams");
+
+        TempDiagrams {
+            diagrams: Diagrams::new(dir.path().to_path_buf()),
+            _dir: dir,
+        }
+    }
+
+    fn options() -> Options {
+        MarkdownOptions::default().to_options()
+    }
 
     #[test]
     fn test_parse_ok() {
         let path = PathBuf::from("./tests/fixtures/2050_05_30.md");
-        let result = parse(path);
+        let result = parse(
+            path,
+            Path::new("./tests/fixtures"),
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        );
 
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_parse_with_table() {
+        let path = PathBuf::from("./tests/fixtures/table.md");
+        let result = parse(
+            path,
+            Path::new("./tests/fixtures"),
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        )
+        .unwrap();
+
+        assert_eq!(result.title, "表のある記事");
+        assert_eq!(result.tags, vec!["rust"]);
+        assert!(result.body.0.contains("<table>"));
+        assert!(result.body.0.contains("<td>2</td>"));
+        assert!(result.body.0.contains("<hr />"));
+        assert!(result.body.0.contains("<p>後半</p>"));
+    }
+
     #[test]
     fn test_parse_with_path() {
         let path = PathBuf::from("./tests/fixtures/2050_05_30.md");
-        let created_time = created_datetime(&path).unwrap();
-        let result = parse(path).unwrap();
+        let created_time = created_datetime(&path, Path::new("./tests/fixtures")).unwrap();
+        let result = parse(
+            path,
+            Path::new("./tests/fixtures"),
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        )
+        .unwrap();
 
-        let html = "<p>本文はありません。</p>\n".to_string();
-        let html_body = HtmlBody(html);
-        let path = format!("{}{}", "タイトル無し", ".html");
+        let html_body = HtmlBody("<p>本文はありません。</p>\n".to_string());
 
         let article = Article {
             id: "2050-05-30_タイトル無し".to_string(),
-            title: "タイトル無し".to_string(),
-            body: html_body,
+            body: html_body.clone(),
+            excerpt: html_body,
+            slug: "2050_05_30".to_string(),
             date: created_time,
-            path,
+            source: PathBuf::from("./tests/fixtures/2050_05_30.md"),
+            ..article("タイトル無し")
         };
 
         assert_eq!(result, article);
     }
 
+    #[test]
+    fn test_parse_with_date_field() {
+        let dir = temp_dir("parse_with_date_field");
+        let path = dir.path().join("rust-tips.md");
+        fs::write(
+            &path,
+            "---\ntitle: Rust tips\ndate: 2023-02-03\n---\n\nbody",
+        )
+        .unwrap();
+
+        let result = parse(
+            path,
+            Path::new("./tests/fixtures"),
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        )
+        .unwrap();
+
+        assert_eq!(result.date, NaiveDate::from_ymd_opt(2023, 2, 3).unwrap());
+        assert!(result.extra.is_empty());
+    }
+
+    #[test]
+    fn test_parse_undated_file_with_modification_date() {
+        let dir = temp_dir("parse_undated");
+        let path = dir.path().join("rust-tips.md");
+        fs::write(&path, "---\ntitle: Rust tips\n---\n\nbody").unwrap();
+        let modified = Local.with_ymd_and_hms(2020, 1, 2, 12, 0, 0).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified.into())
+            .unwrap();
+
+        let result = parse(
+            path,
+            dir.path(),
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        )
+        .unwrap();
+
+        assert_eq!(result.date, NaiveDate::from_ymd_opt(2020, 1, 2).unwrap());
+    }
+
+    #[test]
+    fn test_parse_page_ok() {
+        let dir = temp_dir("parse_page");
+        let root = dir.path();
+        let path = root.join("about/me.md");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            "---\ntitle: About\nemail: me@example.com\n---\n\nHello",
+        )
+        .unwrap();
+
+        let result = parse_page(
+            &path,
+            root,
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        )
+        .unwrap();
+
+        assert_eq!(result.title, "About");
+        assert_eq!(result.path, "about/me.html");
+        assert_eq!(result.body.0, "<p>Hello</p>\n");
+        assert_eq!(result.extra.get("email").unwrap(), "me@example.com");
+    }
+
+    #[test]
+    fn test_parse_page_without_title() {
+        let dir = temp_dir("parse_page_without_title");
+        let root = dir.path();
+        let path = root.join("uses.md");
+        fs::write(&path, "Tools I use").unwrap();
+
+        let result = parse_page(
+            &path,
+            root,
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        )
+        .unwrap();
+
+        assert_eq!(result.title, "uses");
+        assert_eq!(result.path, "uses.html");
+    }
+
     #[test]
     fn test_convert_md_to_html_ok() {
         let content = "Hello world, this is a ~~complicated~~ *very simple* example.";
-        let result = convert_md_to_html(content).unwrap();
+        let result = convert_md_to_html(content, options(), &diagrams()).unwrap();
 
         let html =
             "<p>Hello world, this is a <del>complicated</del> <em>very simple</em> example.</p>\n"
@@ -129,6 +656,223 @@ mod tests {
         assert_eq!(result.0, expected_html.0);
     }
 
+    #[test]
+    fn test_convert_md_to_html_with_code_block() {
+        let content = "```rust {2}\nlet a = 1;\nlet b = 2;\n```\n";
+        let result = convert_md_to_html(content, options(), &diagrams()).unwrap();
+
+        assert!(result.0.contains(r#"<code class="language-rust">"#));
+        assert!(result
+            .0
+            .contains(r#"<span class="line highlighted" data-line="2">"#));
+    }
+
+    #[test]
+    fn test_render_markdown_with_headings() {
+        let content = "## 年末年始\n\n### Step `one`\n\n## 年末年始\n\n# Custom {#intro}\n";
+        let (html_body, headings) = render_markdown(content, options(), &diagrams()).unwrap();
+
+        assert!(html_body.0.contains(
+            r##"<h2 id="年末年始">年末年始<a class="anchor" href="#年末年始" aria-label="#"></a></h2>"##
+        ));
+        let ids: Vec<&str> = headings.iter().map(|heading| heading.id.as_str()).collect();
+        assert_eq!(ids, vec!["年末年始", "step-one", "年末年始-1", "intro"]);
+        assert_eq!(headings[1].title, "Step one");
+        assert_eq!(headings[1].level, 3);
+    }
+
+    #[test]
+    fn test_render_markdown_escapes_anchor() {
+        let (html_body, _) = render_markdown("## Q&A {#q&a}\n", options(), &diagrams()).unwrap();
+
+        assert_eq!(
+            html_body.0,
+            "<h2 id=\"q&amp;a\">Q&amp;A<a class=\"anchor\" href=\"#q&amp;a\" aria-label=\"#\"></a></h2>\n"
+        );
+    }
+
+    #[test]
+    fn test_convert_md_to_html_with_math() {
+        let content = "Euler: $e^{i\\pi} + 1 = 0$\n\n$$\n\\sum_{k=1}^n k\n$$\n";
+        let options = MarkdownOptions {
+            math: true,
+            ..MarkdownOptions::default()
+        };
+        let result = convert_md_to_html(content, options.to_options(), &diagrams()).unwrap();
+
+        assert!(result.0.contains("<math"));
+        assert!(result.0.contains(r#"<math display="block""#));
+        assert!(!result.0.contains('$'));
+    }
+
+    #[test]
+    fn test_convert_md_to_html_without_math() {
+        let content = "From $5 to $10, or `$HOME`\n";
+        let result = convert_md_to_html(content, options(), &diagrams()).unwrap();
+
+        assert_eq!(result.0, "<p>From $5 to $10, or <code>$HOME</code></p>\n");
+    }
+
+    #[test]
+    fn test_parse_with_invalid_math() {
+        let dir = temp_dir("invalid_math");
+        let path = dir.path().join("invalid_math.md");
+        fs::write(
+            &path,
+            "---\ntitle: math\nmarkdown.math: true\n---\n\nok\n\n$\\frac{a}$\n",
+        )
+        .unwrap();
+
+        let result = parse(
+            path.clone(),
+            dir.path(),
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        );
+
+        match result {
+            Err(ChabloError::SourceError {
+                path: file,
+                line,
+                source,
+            }) => {
+                assert_eq!(file, path.display().to_string());
+                assert_eq!(line, 8);
+                assert!(matches!(*source, ChabloError::MathError(_)));
+            }
+            result => panic!("expected a math error: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_with_invalid_math_after_shortcode() {
+        let dir = temp_dir("invalid_math_after_shortcode");
+        let path = dir.path().join("invalid_math_after_shortcode.md");
+        // The video expands to three lines, which must not shift the line of the error
+        fs::write(
+            &path,
+            "---\ntitle: math\nmarkdown.math: true\n---\n\n{{< youtube abc >}}\n\n$\\frac{a}$\n",
+        )
+        .unwrap();
+
+        let result = parse(
+            path.clone(),
+            dir.path(),
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        );
+
+        match result {
+            Err(ChabloError::SourceError { line, source, .. }) => {
+                assert_eq!(line, 8);
+                assert!(matches!(*source, ChabloError::MathError(_)));
+            }
+            result => panic!("expected a math error: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_convert_md_to_html_with_diagram() {
+        let content = "```dot\ndigraph { a -> b }\n```\n\n```mermaid\ngraph TD; A-->B\n```\n";
+        let result = convert_md_to_html(content, options(), &diagrams()).unwrap();
+
+        assert!(result.0.contains(r#"<figure class="diagram"><svg"#));
+        // Kept as a code block unless mermaid-cli is installed
+        assert!(result.0.contains("language-mermaid") || result.0.matches("<svg").count() == 2);
+    }
+
+    #[test]
+    fn test_parse_with_shortcode() {
+        let dir = temp_dir("shortcode");
+        let path = dir.path().join("shortcode.md");
+        fs::write(
+            &path,
+            "---\ntitle: embed\n---\n\n{{< youtube abc >}}\n\n{{< youtube >}}\n",
+        )
+        .unwrap();
+
+        let result = parse(
+            path.clone(),
+            dir.path(),
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        );
+
+        match result {
+            Err(error @ ChabloError::SourceError { .. }) => {
+                let message = error.to_string();
+                assert!(message.starts_with(&format!("{}:7: ", path.display())));
+                assert!(message.contains("`youtube`"));
+            }
+            result => panic!("expected a shortcode error: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_convert_md_to_html_keeps_internal_links() {
+        let result =
+            convert_md_to_html("[前回](@/diary/2023/01/07.md#初詣)", options(), &diagrams())
+                .unwrap();
+
+        assert!(result
+            .0
+            .contains(r#"href="@/diary/2023/01/07.md#%E5%88%9D%E8%A9%A3""#));
+    }
+
+    #[test]
+    fn test_parse_bundle() {
+        let root = temp_dir("parse_bundle");
+        let dir = root.path().join("2023/04/07");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("index.md"),
+            "---\ntitle: 花見\ndate: 2023-04-07\n---\n\n![桜](sakura.jpg)\n",
+        )
+        .unwrap();
+
+        let result = parse(
+            dir.join("index.md"),
+            root.path(),
+            &MarkdownOptions::default(),
+            &Shortcodes::default(),
+            &diagrams(),
+        )
+        .unwrap();
+
+        assert_eq!(result.slug, "07");
+        assert_eq!(result.date, NaiveDate::from_ymd_opt(2023, 4, 7).unwrap());
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("ブログ システム"), "ブログ-システム");
+        assert_eq!(slugify("!?"), "section");
+    }
+
+    #[test]
+    fn test_convert_md_to_html_with_gfm() {
+        let content = "| a |\n|---|\n| b |\n\n- [x] done\n\nnote[^1]\n\n[^1]: footnote\n";
+        let result = convert_md_to_html(content, options(), &diagrams()).unwrap();
+
+        assert!(result.0.contains("<table>"));
+        assert!(result
+            .0
+            .contains(r#"<input disabled="" type="checkbox" checked=""/>"#));
+        assert!(result.0.contains(r#"<sup class="footnote-reference">"#));
+    }
+
+    #[test]
+    fn test_convert_md_to_html_without_tables() {
+        let content = "| a |\n|---|\n| b |\n";
+        let result = convert_md_to_html(content, Options::empty(), &diagrams()).unwrap();
+
+        assert!(!result.0.contains("<table>"));
+    }
+
     #[test]
     fn test_extract_title_ok() {
         let content: &str = "---\ntitle: dummy title\n---\n\nThis is a dummy body.\n\n\n改行されているか。\n\n\noooooooooooooooo
@@ -163,9 +907,125 @@ oooooooooooooooooooooooooooooo\n\n\noooooooooooo\n";
         assert_eq!(result, expected_title);
     }
 
+    #[test]
+    fn test_extract_title_with_other_fields() {
+        let content: &str = "---\ntitle: dummy title\ndescription: summary\n---\n\nbody";
+
+        let result = extract_title(content).unwrap();
+        let expected_title = "dummy title";
+
+        assert_eq!(result, expected_title);
+    }
+
+    #[test]
+    fn test_extract_front_matter_ok() {
+        let content: &str = "---\ntitle: dummy title\ndescription: \"a: b\"\n---\n\nbody";
+
+        let result = extract_front_matter(content);
+
+        assert_eq!(result.get("title"), Some("dummy title"));
+        assert_eq!(result.get("description"), Some("a: b"));
+        assert_eq!(result.get("image"), None);
+    }
+
+    #[test]
+    fn test_extract_excerpt_with_description() {
+        let content: &str = "---\ntitle: dummy title\ndescription: *要約*です\n---\n\n本文";
+        let front_matter = extract_front_matter(content);
+        let body = extract_body(content).unwrap();
+        let html_body = convert_md_to_html(body, options(), &diagrams()).unwrap();
+
+        let result =
+            extract_excerpt(&front_matter, body, &html_body, options(), &diagrams()).unwrap();
+
+        assert_eq!(result.0, "<p><em>要約</em>です</p>\n");
+    }
+
+    #[test]
+    fn test_extract_excerpt_with_more_marker() {
+        let body = "最初の段落\n\n<!-- more -->\n\n続き";
+        let html_body = convert_md_to_html(body, options(), &diagrams()).unwrap();
+
+        let result = extract_excerpt(
+            &FrontMatter::default(),
+            body,
+            &html_body,
+            options(),
+            &diagrams(),
+        )
+        .unwrap();
+
+        assert_eq!(result.0, "<p>最初の段落</p>\n");
+    }
+
+    #[test]
+    fn test_extract_excerpt_without_heading_anchors() {
+        let body = "## はじめに\n\n最初の段落\n\n<!-- more -->\n\n続き";
+        let html_body = convert_md_to_html(body, options(), &diagrams()).unwrap();
+
+        let summary = extract_excerpt(
+            &FrontMatter::default(),
+            body,
+            &html_body,
+            options(),
+            &diagrams(),
+        )
+        .unwrap();
+        let truncated = extract_excerpt(
+            &FrontMatter::default(),
+            "",
+            &html_body,
+            options(),
+            &diagrams(),
+        )
+        .unwrap();
+
+        assert_eq!(summary.0, "<h2>はじめに</h2>\n<p>最初の段落</p>\n");
+        assert!(!truncated.0.contains("id=") && !truncated.0.contains("anchor"));
+    }
+
+    #[test]
+    fn test_extract_image_from_front_matter() {
+        let content: &str = "---\ntitle: dummy title\nimage: /img/cover.png\n---\n\n![a](b.png)";
+        let front_matter = extract_front_matter(content);
+        let html_body =
+            convert_md_to_html(extract_body(content).unwrap(), options(), &diagrams()).unwrap();
+
+        let result = extract_image(&front_matter, &html_body);
+
+        assert_eq!(result, Some("/img/cover.png".to_string()));
+    }
+
+    #[test]
+    fn test_extract_image_from_body() {
+        let html_body = convert_md_to_html(
+            "text\n\n![a](first.png) ![b](second.png)",
+            options(),
+            &diagrams(),
+        )
+        .unwrap();
+
+        let result = extract_image(&FrontMatter::default(), &html_body);
+
+        assert_eq!(result, Some("first.png".to_string()));
+    }
+
+    #[test]
+    fn test_extract_tags() {
+        let front_matter = extract_front_matter("---\ntitle: t\ntags: [rust, \"日記\"]\n---\n");
+        let result = extract_tags(&front_matter);
+
+        assert_eq!(result, vec!["rust", "日記"]);
+
+        let front_matter = extract_front_matter("---\ntitle: t\ntags: book,\n---\n");
+        let result = extract_tags(&front_matter);
+
+        assert_eq!(result, vec!["book"]);
+    }
+
     #[test]
     fn test_extract_body_ok() {
-        let content: &str = "---\ntitle: dummy title\n---This is a dummy body.";
+        let content: &str = "---\ntitle: dummy title\n---\nThis is a dummy body.";
 
         let result = extract_body(content).unwrap();
         let expected_body = "This is a dummy body.";
@@ -173,6 +1033,25 @@ oooooooooooooooooooooooooooooo\n\n\noooooooooooo\n";
         assert_eq!(result, expected_body);
     }
 
+    #[test]
+    fn test_extract_body_with_table() {
+        let content: &str = "---\ntitle: 表\n---\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n---\n\n後半";
+
+        let result = extract_body(content).unwrap();
+
+        assert_eq!(result, "\n| a | b |\n|---|---|\n| 1 | 2 |\n\n---\n\n後半");
+    }
+
+    #[test]
+    fn test_extract_body_without_front_matter() {
+        let content: &str = "前半\n\n---\n\n後半";
+
+        let result = extract_body(content).unwrap();
+
+        assert_eq!(result, content);
+        assert_eq!(extract_title(content).unwrap(), "タイトル無し");
+    }
+
     #[test]
     fn test_extract_body_with_different_format() {
         let content: &str = "Hello world, this is a ~~complicated~~ *very simple* example.";
@@ -1,13 +1,17 @@
 //! http server
-use std::fs::File;
-use std::io::{prelude::*, Read};
+use std::fs;
+use std::io::prelude::*;
 use std::net::{TcpListener, TcpStream};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
 use log::{error, info, warn};
 
+use crate::compress::{is_compressible, sibling, ENCODINGS};
 use crate::errors::ChabloError;
 
+/// Directory served, as built by `chablo build`
+const SERVED_DIR: &str = "public";
+
 pub fn serve() -> Result<(), ChabloError> {
     let address = "localhost:8080";
     let listener = TcpListener::bind(address)?;
@@ -52,6 +56,12 @@ fn handle_connection(mut stream: TcpStream) -> Result<(), ChabloError> {
     let binding = String::from_utf8_lossy(&buffer[..]);
     let request_line = binding.lines().next().unwrap_or("");
     let request_path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let accept_encoding = binding.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("accept-encoding")
+            .then(|| value.trim())
+    });
     let decoded_path = decode_percent_encoded_string(request_path)?;
 
     let request_path = if decoded_path == "/" {
@@ -59,31 +69,97 @@ fn handle_connection(mut stream: TcpStream) -> Result<(), ChabloError> {
     } else {
         &decoded_path
     };
-    let filename = format!("./public{}", request_path);
 
-    // Check if the file exists and is a .html file
-    let (status_line, filename) = if Path::new(&filename).exists() && filename.ends_with(".html") {
-        ("HTTP/1.1 200 OK\r\n\r\n", filename)
-    } else {
-        (
-            "HTTP/1.1 404 NOT FOUND\r\n\r\n",
-            "./public/404.html".to_string(),
-        )
+    // Check if the file exists under `public/`, pages being served along with their
+    // stylesheets, scripts and source maps
+    let (status_line, filename) = match resolve_path(Path::new(SERVED_DIR), request_path) {
+        Some(path) => ("HTTP/1.1 200 OK", path),
+        None => (
+            "HTTP/1.1 404 NOT FOUND",
+            Path::new(SERVED_DIR).join("404.html"),
+        ),
     };
 
-    let mut file = File::open(filename)?;
-    let mut contents = String::new();
-
-    file.read_to_string(&mut contents)?;
+    let content_type = mime_guess::from_path(&filename).first_or_octet_stream();
+    let mut headers = format!("{}\r\nContent-Type: {}\r\n", status_line, content_type);
+    let mut body_path = filename;
+    if is_compressible(&body_path) {
+        // Caches must not serve a compressed response to clients not accepting it
+        headers.push_str("Vary: Accept-Encoding\r\n");
+        if let Some((encoding, compressed)) = negotiate_encoding(&body_path, accept_encoding) {
+            headers.push_str(&format!("Content-Encoding: {}\r\n", encoding));
+            body_path = compressed;
+        }
+    }
+    headers.push_str("\r\n");
 
-    let response = format!("{}{}", status_line, contents);
+    let contents = match fs::read(&body_path) {
+        Ok(contents) => contents,
+        // A site without a 404 page
+        Err(_) if status_line.contains("404") => b"Not Found".to_vec(),
+        Err(e) => return Err(e.into()),
+    };
 
-    stream.write_all(response.as_bytes())?;
+    stream.write_all(headers.as_bytes())?;
+    stream.write_all(&contents)?;
     stream.flush()?;
 
     Ok(())
 }
 
+/// File under `root` requested with `request_path`. Paths leaving `root`, with `..`
+/// or through a symbolic link, are refused.
+fn resolve_path(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let relative = Path::new(request_path.trim_start_matches('/'));
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    let root = fs::canonicalize(root).ok()?;
+    let path = fs::canonicalize(root.join(relative)).ok()?;
+
+    (path.starts_with(&root) && path.is_file()).then_some(path)
+}
+
+/// Precompressed copy of `path` in the preferred encoding the client accepts
+fn negotiate_encoding(
+    path: &Path,
+    accept_encoding: Option<&str>,
+) -> Option<(&'static str, PathBuf)> {
+    let qualities = encoding_qualities(accept_encoding?);
+    let accepts = |encoding: &str| {
+        let quality = |name: &str| qualities.iter().find(|(n, _)| n == name).map(|(_, q)| *q);
+        quality(encoding)
+            .or_else(|| quality("*"))
+            .is_some_and(|q| q > 0.0)
+    };
+
+    ENCODINGS
+        .iter()
+        .filter(|(encoding, _)| accepts(encoding))
+        .map(|(encoding, extension)| (*encoding, sibling(path, extension)))
+        .find(|(_, compressed)| compressed.is_file())
+}
+
+/// Encodings listed in an `Accept-Encoding` header with their quality
+fn encoding_qualities(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let encoding = params.next()?.trim().to_ascii_lowercase();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            (!encoding.is_empty()).then_some((encoding, quality))
+        })
+        .collect()
+}
+
 fn decode_percent_encoded_string(encoded: &str) -> Result<String, ChabloError> {
     let mut bytes = Vec::new();
     let mut chars = encoded.chars();
@@ -100,3 +176,81 @@ fn decode_percent_encoded_string(encoded: &str) -> Result<String, ChabloError> {
 
     Ok(String::from_utf8_lossy(&bytes).to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_resolve_path() {
+        let root = Path::new("tests/fixtures/serve/public");
+
+        let result = resolve_path(root, "/index.html");
+
+        assert_eq!(result, fs::canonicalize(root.join("index.html")).ok());
+    }
+
+    #[test]
+    fn test_resolve_path_refuses_parent_directories() {
+        let root = Path::new("tests/fixtures/serve/public");
+
+        assert_eq!(resolve_path(root, "/../secret.txt"), None);
+        assert_eq!(resolve_path(root, "/css/../../secret.txt"), None);
+        assert_eq!(resolve_path(root, "/../../../etc/hostname"), None);
+        assert_eq!(resolve_path(root, "//etc/hostname"), None);
+    }
+
+    #[test]
+    fn test_resolve_path_refuses_links_out_of_root() {
+        let root = Path::new("tests/fixtures/serve/public");
+
+        // `escape.txt` is a symbolic link to `../secret.txt`
+        let result = resolve_path(root, "/escape.txt");
+
+        assert!(root.join("escape.txt").is_file());
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_encoding_qualities() {
+        let result = encoding_qualities("gzip, deflate;q=0.5, BR;q=0");
+
+        assert_eq!(
+            result,
+            vec![
+                ("gzip".to_string(), 1.0),
+                ("deflate".to_string(), 0.5),
+                ("br".to_string(), 0.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_negotiate_encoding() {
+        let dir = temp_dir("negotiate_encoding");
+        let page = dir.path().join("index.html");
+        let only_gzip = dir.path().join("feed.xml");
+        for path in [&page, &sibling(&page, "gz"), &sibling(&page, "br")] {
+            fs::write(path, "").unwrap();
+        }
+        fs::write(sibling(&only_gzip, "gz"), "").unwrap();
+
+        let brotli = negotiate_encoding(&page, Some("gzip, deflate, br"));
+        let gzip = negotiate_encoding(&page, Some("gzip, br;q=0"));
+        let any = negotiate_encoding(&page, Some("*"));
+        let any_but_brotli = negotiate_encoding(&page, Some("*, br;q=0"));
+        let identity = negotiate_encoding(&page, Some("identity"));
+        let missing_header = negotiate_encoding(&page, None);
+        let fallback = negotiate_encoding(&only_gzip, Some("br, gzip"));
+
+        assert_eq!(brotli, Some(("br", sibling(&page, "br"))));
+        assert_eq!(gzip, Some(("gzip", sibling(&page, "gz"))));
+        assert_eq!(any, Some(("br", sibling(&page, "br"))));
+        assert_eq!(any_but_brotli, Some(("gzip", sibling(&page, "gz"))));
+        assert_eq!(identity, None);
+        assert_eq!(missing_header, None);
+        assert_eq!(fallback, Some(("gzip", sibling(&only_gzip, "gz"))));
+    }
+}
@@ -0,0 +1,479 @@
+//! Shortcodes such as `{{< youtube id >}}` expanded in markdown before it is converted
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use minijinja::{escape_formatter, AutoEscape, Environment, UndefinedBehavior, Value};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+use crate::errors::ChabloError;
+use crate::theme::Theme;
+
+/// Directory of the shortcode templates, relative to the templates of a theme
+pub const SHORTCODES_DIR: &str = "shortcodes";
+
+const OPEN: &str = "{{<";
+const CLOSE: &str = ">}}";
+
+/// Shortcodes shipped with chablo, overridable by the theme
+const BUILTIN_SHORTCODES: [(&str, &str); 3] = [
+    (
+        "youtube.html",
+        include_str!("../templates/shortcodes/youtube.html"),
+    ),
+    (
+        "figure.html",
+        include_str!("../templates/shortcodes/figure.html"),
+    ),
+    (
+        "note.html",
+        include_str!("../templates/shortcodes/note.html"),
+    ),
+];
+
+/// Lines of expanded markdown mapped back to the lines of its source
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineMap(Vec<Segment>);
+
+/// Stretch of the expanded markdown, copied from the source or rendered by a shortcode
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Segment {
+    /// Line of the expanded markdown the stretch starts at
+    line: usize,
+    /// Line of the source it comes from
+    source_line: usize,
+    /// Whether its lines follow the lines of the source one to one
+    verbatim: bool,
+}
+
+impl LineMap {
+    /// Line of the source an expanded line comes from. Lines rendered by a shortcode map
+    /// to the line of the shortcode.
+    pub fn source_line(&self, line: usize) -> usize {
+        match self.0.iter().rev().find(|segment| segment.line <= line) {
+            Some(segment) if segment.verbatim => segment.source_line + line - segment.line,
+            Some(segment) => segment.source_line,
+            None => line,
+        }
+    }
+
+    /// Point an error raised at a line of the expanded markdown to its source line
+    pub fn locate(&self, error: ChabloError) -> ChabloError {
+        match error {
+            ChabloError::SourceError { path, line, source } => ChabloError::SourceError {
+                path,
+                line: self.source_line(line),
+                source,
+            },
+            error => error,
+        }
+    }
+
+    fn push(&mut self, output: &str, source_line: usize, verbatim: bool) {
+        self.0.push(Segment {
+            line: output.matches('\n').count() + 1,
+            source_line,
+            verbatim,
+        });
+    }
+}
+
+/// Templates of the shortcodes. A template receives the positional arguments as `args`,
+/// the named ones as variables and the inner markdown of `{{< name >}}...{{< /name >}}`
+/// as `body`. Referring to a missing argument is an error.
+#[derive(Debug)]
+pub struct Shortcodes {
+    env: Environment<'static>,
+}
+
+impl Default for Shortcodes {
+    fn default() -> Self {
+        Self::with_dir(None)
+    }
+}
+
+impl Shortcodes {
+    /// Shortcodes of `templates/shortcodes/` in the theme, falling back to the built-in ones
+    pub fn new(theme: &Theme) -> Self {
+        match theme {
+            Theme::Builtin => Self::default(),
+            Theme::Runtime { dir, .. } => {
+                Self::with_dir(Some(dir.join("templates").join(SHORTCODES_DIR)))
+            }
+        }
+    }
+
+    fn with_dir(dir: Option<PathBuf>) -> Self {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        // Unlike the default escaping, slashes are kept so that URLs still resolve
+        env.set_formatter(|out, state, value| {
+            if value.is_safe()
+                || value.is_undefined()
+                || value.is_none()
+                || state.auto_escape() != AutoEscape::Html
+            {
+                return escape_formatter(out, state, value);
+            }
+            out.write_str(&escape_attribute(&value.to_string()))
+                .map_err(|e| {
+                    minijinja::Error::new(minijinja::ErrorKind::WriteFailure, e.to_string())
+                })
+        });
+        env.set_loader(move |name| {
+            if let Some(path) = dir.as_ref().map(|dir| dir.join(name)) {
+                if path.is_file() {
+                    return fs::read_to_string(&path).map(Some).map_err(|e| {
+                        minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, e.to_string())
+                    });
+                }
+            }
+
+            Ok(BUILTIN_SHORTCODES
+                .iter()
+                .find(|(builtin, _)| *builtin == name)
+                .map(|(_, source)| source.to_string()))
+        });
+
+        Shortcodes { env }
+    }
+
+    /// Expand every shortcode of `md` outside code blocks and code spans, mapping the
+    /// lines of the result to the lines of `md`. Errors are reported with the line of
+    /// `md` the shortcode starts at.
+    pub fn expand(&self, md: &str) -> Result<(String, LineMap), ChabloError> {
+        let mut lines = LineMap::default();
+        let output = self.expand_from(md, 1, &mut lines)?;
+
+        Ok((output, lines))
+    }
+
+    fn expand_from(
+        &self,
+        md: &str,
+        first_line: usize,
+        lines: &mut LineMap,
+    ) -> Result<String, ChabloError> {
+        let code = code_ranges(md);
+        let mut output = String::new();
+        let mut cursor = 0;
+        let mut search = 0;
+
+        while let Some(start) = find_tag(md, search, &code) {
+            let line = line_at(md, start, first_line);
+            let error = |message: String| ChabloError::SourceError {
+                path: String::new(),
+                line,
+                source: Box::new(ChabloError::ShortcodeError(message)),
+            };
+            let (inner, mut end) = read_tag(md, start)
+                .ok_or_else(|| error(format!("`{OPEN}` is not closed by `{CLOSE}`")))?;
+            lines.push(&output, line_at(md, cursor, first_line), true);
+            output.push_str(&md[cursor..start]);
+            lines.push(&output, line, false);
+
+            if let Some(literal) = inner.strip_prefix("/*").and_then(|s| s.strip_suffix("*/")) {
+                // `{{</* name */>}}` is written out as is
+                output.push_str(&format!("{OPEN} {} {CLOSE}", literal.trim()));
+            } else {
+                let (name, args) = parse_tag(inner).map_err(error)?;
+                if name.starts_with('/') {
+                    return Err(error(format!("unexpected closing shortcode `{name}`")));
+                }
+
+                let mut context: BTreeMap<String, Value> = BTreeMap::new();
+                let mut positional: Vec<String> = vec![];
+                for (key, value) in args {
+                    match key {
+                        Some(key) => {
+                            context.insert(key, Value::from(value));
+                        }
+                        None => positional.push(value),
+                    }
+                }
+                context.insert("args".to_string(), Value::from(positional));
+
+                if let Some((body, after)) = find_closing(md, end, &name, &code) {
+                    let body_line = line_at(md, end, first_line);
+                    // Lines of the body are not kept as is by the template
+                    let body =
+                        self.expand_from(&md[end..body], body_line, &mut LineMap::default())?;
+                    context.insert("body".to_string(), Value::from_safe_string(body));
+                    end = after;
+                }
+
+                let template = self
+                    .env
+                    .get_template(&format!("{name}.html"))
+                    .map_err(|_| error(format!("unknown shortcode `{name}`")))?;
+                let html = template
+                    .render(&context)
+                    .map_err(|e| error(format!("`{name}`: {e}")))?;
+                output.push_str(&html);
+            }
+
+            cursor = end;
+            search = end;
+        }
+        lines.push(&output, line_at(md, cursor, first_line), true);
+        output.push_str(&md[cursor..]);
+
+        Ok(output)
+    }
+}
+
+/// Line of `md` at the byte `offset`, `md` starting at `first_line`
+fn line_at(md: &str, offset: usize, first_line: usize) -> usize {
+    first_line + md[..offset].matches('\n').count()
+}
+
+/// Offset of the next `{{<` from `search` outside the `code` ranges
+fn find_tag(md: &str, mut search: usize, code: &[Range<usize>]) -> Option<usize> {
+    while let Some(offset) = md[search..].find(OPEN) {
+        let start = search + offset;
+        match code.iter().find(|range| range.contains(&start)) {
+            Some(range) => search = range.end,
+            None => return Some(start),
+        }
+    }
+
+    None
+}
+
+/// Inner text of the tag starting at `start` and the offset right after it
+fn read_tag(md: &str, start: usize) -> Option<(&str, usize)> {
+    let inner_start = start + OPEN.len();
+    let length = md[inner_start..].find(CLOSE)?;
+    let inner = md[inner_start..inner_start + length].trim();
+
+    Some((inner, inner_start + length + CLOSE.len()))
+}
+
+/// Body range end and the offset after `{{< /name >}}`, searching from `from`
+fn find_closing(
+    md: &str,
+    from: usize,
+    name: &str,
+    code: &[Range<usize>],
+) -> Option<(usize, usize)> {
+    let mut search = from;
+    while let Some(start) = find_tag(md, search, code) {
+        let (inner, end) = read_tag(md, start)?;
+        if inner.strip_prefix('/').map(str::trim) == Some(name) {
+            return Some((start, end));
+        }
+        search = end;
+    }
+
+    None
+}
+
+/// Argument of a shortcode, with its key unless it is positional
+type Arg = (Option<String>, String);
+
+/// Split `name arg key="quoted value"` into the name and its arguments
+fn parse_tag(inner: &str) -> Result<(String, Vec<Arg>), String> {
+    let mut tokens = split_args(inner)?.into_iter();
+    let name = tokens.next().ok_or("missing shortcode name")?;
+    if name.contains('=')
+        || !name
+            .trim_start_matches('/')
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+    {
+        return Err(format!("invalid shortcode name `{name}`"));
+    }
+
+    let args = tokens
+        .map(|token| match token.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                (Some(key.to_string()), value.trim_matches('"').to_string())
+            }
+            _ => (None, token.trim_matches('"').to_string()),
+        })
+        .collect();
+
+    Ok((name, args))
+}
+
+/// Split on whitespace outside double quotes
+fn split_args(inner: &str) -> Result<Vec<String>, String> {
+    let mut tokens: Vec<String> = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+
+    for ch in inner.chars() {
+        match ch {
+            '"' => {
+                quoted = !quoted;
+                token.push(ch);
+            }
+            _ if ch.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            _ => token.push(ch),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote in shortcode arguments".to_string());
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Byte ranges of the fenced and indented code blocks and of the code spans, where
+/// shortcodes are left alone
+fn code_ranges(md: &str) -> Vec<Range<usize>> {
+    Parser::new_ext(md, Options::empty())
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::Start(Tag::CodeBlock(_)) | Event::Code(_)))
+        .map(|(_, range)| range)
+        .collect()
+}
+
+/// Escape the characters with a meaning in HTML text and quoted attributes
+fn escape_attribute(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn expand(md: &str) -> Result<String, ChabloError> {
+        Shortcodes::default().expand(md).map(|(md, _)| md)
+    }
+
+    fn shortcode_error(result: Result<String, ChabloError>) -> (usize, String) {
+        match result {
+            Err(ChabloError::SourceError { line, source, .. }) => (line, source.to_string()),
+            result => panic!("expected a shortcode error: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_expand_youtube() {
+        let result = expand("before\n\n{{< youtube dQw4w9WgXcQ >}}\n").unwrap();
+
+        assert!(result.starts_with("before\n\n<div class=\"video\">"));
+        assert!(result.contains("https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ"));
+    }
+
+    #[test]
+    fn test_expand_figure() {
+        let result = expand(r#"{{< figure src="/img/cat.png" caption="うちの猫" >}}"#).unwrap();
+
+        assert!(result.contains(r#"<img src="/img/cat.png" alt="うちの猫" loading="lazy">"#));
+        assert!(result.contains("<figcaption>うちの猫</figcaption>"));
+    }
+
+    #[test]
+    fn test_expand_escapes_arguments() {
+        let result = expand(r#"{{< figure src="/a b.png" caption="<b>'&'</b>" >}}"#).unwrap();
+
+        assert!(result.contains(r#"src="/a b.png""#));
+        assert!(result.contains("<figcaption>&lt;b&gt;&#x27;&amp;&#x27;&lt;/b&gt;</figcaption>"));
+    }
+
+    #[test]
+    fn test_expand_note_with_body() {
+        let result =
+            expand("{{< note kind=warning >}}\n**Careful** {{< youtube abc >}}\n{{< /note >}}")
+                .unwrap();
+
+        assert!(result.starts_with("<div class=\"note note-warning\">\n\n"));
+        assert!(result.contains("**Careful** <div class=\"video\">"));
+        assert!(result.ends_with("</div>"));
+    }
+
+    #[test]
+    fn test_expand_skips_code_blocks() {
+        let content = "```\n{{< youtube abc >}}\n```\n{{</* youtube abc */>}}";
+        let result = expand(content).unwrap();
+
+        assert_eq!(result, "```\n{{< youtube abc >}}\n```\n{{< youtube abc >}}");
+    }
+
+    #[test]
+    fn test_expand_skips_code_spans_and_indented_code() {
+        let content =
+            "Write `{{< youtube abc >}}` or\n\n    {{< youtube abc >}}\n\n{{< youtube x >}}";
+        let result = expand(content).unwrap();
+
+        assert!(result.starts_with("Write `{{< youtube abc >}}` or\n\n    {{< youtube abc >}}\n\n"));
+        assert!(result.contains("embed/x"));
+        assert!(!result.contains("embed/abc"));
+    }
+
+    #[test]
+    fn test_expand_maps_lines_to_source() {
+        let content = "a\n{{< note >}}\nb\n{{< /note >}}\nc\n\nd";
+        let (result, lines) = Shortcodes::default().expand(content).unwrap();
+        let line_of = |text: &str| {
+            result
+                .lines()
+                .position(|line| line == text)
+                .map(|i| lines.source_line(i + 1))
+        };
+
+        assert!(result.matches('\n').count() > content.matches('\n').count());
+        assert_eq!(line_of("a"), Some(1));
+        assert_eq!(line_of("b"), Some(2));
+        assert_eq!(line_of("c"), Some(5));
+        assert_eq!(line_of("d"), Some(7));
+    }
+
+    #[test]
+    fn test_expand_with_missing_argument() {
+        let result = expand("line\n\n{{< figure caption=\"cat\" >}}");
+        let (line, message) = shortcode_error(result);
+
+        assert_eq!(line, 3);
+        assert!(message.contains("`figure`"));
+    }
+
+    #[test]
+    fn test_expand_with_unknown_shortcode() {
+        let (line, message) = shortcode_error(expand("{{< tweet 1 >}}"));
+
+        assert_eq!(line, 1);
+        assert!(message.contains("unknown shortcode `tweet`"));
+    }
+
+    #[test]
+    fn test_expand_with_unclosed_tag() {
+        let (_, message) = shortcode_error(expand("{{< youtube abc"));
+
+        assert!(message.contains("is not closed"));
+    }
+
+    #[test]
+    fn test_shortcodes_from_theme() {
+        let theme = Theme::from_dir(PathBuf::from("tests/fixtures/themes/simple")).unwrap();
+        let result = Shortcodes::new(&theme)
+            .expand("{{< youtube abc >}}")
+            .unwrap()
+            .0;
+
+        assert_eq!(result, r#"<lite-youtube videoid="abc"></lite-youtube>"#);
+    }
+}
@@ -0,0 +1,34 @@
+//! Helpers shared by the unit tests
+use std::path::PathBuf;
+
+use tempfile::TempDir;
+
+use crate::models::{curent_datetime, Article, HtmlBody};
+
+/// Directory of its own for a test, so that concurrent runs do not collide. It is
+/// removed when dropped, even if the test fails.
+pub fn temp_dir(name: &str) -> TempDir {
+    tempfile::Builder::new()
+        .prefix(&format!("chablo_{name}"))
+        .tempdir()
+        .expect("temporary directory")
+}
+
+/// Article of the `diary` section, for tests to adjust with the struct update syntax
+pub fn article(title: &str) -> Article {
+    Article {
+        id: "id".to_string(),
+        title: title.to_string(),
+        body: HtmlBody("<p>body</p>".to_string()),
+        excerpt: HtmlBody("<p>body</p>".to_string()),
+        image: None,
+        tags: vec![],
+        extra: Default::default(),
+        toc: vec![],
+        section: "diary".to_string(),
+        slug: "slug".to_string(),
+        date: curent_datetime(),
+        path: format!("{title}.html"),
+        source: PathBuf::from("diary/test.md"),
+    }
+}
@@ -0,0 +1,180 @@
+//! Themes rendered at runtime
+use std::path::{Path, PathBuf};
+
+use askama::Template;
+use minijinja::{path_loader, Environment, ErrorKind, Value};
+use serde::Serialize;
+
+use crate::assets::AssetManifest;
+use crate::errors::ChabloError;
+use crate::writer::Outputs;
+
+pub const THEMES_DIR: &str = "themes";
+
+#[derive(Debug, Default)]
+pub enum Theme {
+    /// Askama templates compiled into chablo
+    #[default]
+    Builtin,
+    /// Templates and static assets under `themes/<name>/`
+    Runtime {
+        dir: PathBuf,
+        env: Box<Environment<'static>>,
+    },
+}
+
+impl Theme {
+    /// Load the theme selected in the config
+    pub fn load(name: Option<&str>) -> Result<Self, ChabloError> {
+        match name {
+            Some(name) => Self::from_dir(Path::new(THEMES_DIR).join(name)),
+            None => Ok(Theme::Builtin),
+        }
+    }
+
+    pub fn from_dir(dir: PathBuf) -> Result<Self, ChabloError> {
+        if !dir.is_dir() {
+            return Err(ChabloError::ThemeNotFound(dir.display().to_string()));
+        }
+        let mut env = Environment::new();
+        env.set_loader(path_loader(dir.join("templates")));
+        let mut theme = Theme::Runtime {
+            dir,
+            env: Box::new(env),
+        };
+        theme.set_assets(AssetManifest::default());
+
+        Ok(theme)
+    }
+
+    /// Resolve `asset_url("css/main.css")` in theme templates with `manifest`
+    pub fn set_assets(&mut self, manifest: AssetManifest) {
+        if let Theme::Runtime { env, .. } = self {
+            env.add_function("asset_url", move |path: &str| {
+                // Percent-encoded URLs need no HTML escaping
+                Value::from_safe_string(manifest.asset_url(path))
+            });
+        }
+    }
+
+    /// Render `template` with the theme's template called `name`, falling back to the
+    /// built-in one compiled from `builtin_name` when the theme does not provide it
+    pub fn render<T: Template + Serialize>(
+        &self,
+        template: &T,
+        name: &str,
+        builtin_name: &str,
+    ) -> Result<String, ChabloError> {
+        if let Theme::Runtime { env, .. } = self {
+            match env.get_template(name) {
+                Ok(theme_template) => return Ok(theme_template.render(template)?),
+                Err(e) if e.kind() == ErrorKind::TemplateNotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if name != builtin_name {
+            return Err(ChabloError::TemplateNotFound(name.to_string()));
+        }
+
+        Ok(template.render()?)
+    }
+
+    /// Copy the `static/` directory of the theme into the output directory
+    pub fn copy_static(&self, output_dir: &str, outputs: &mut Outputs) -> Result<(), ChabloError> {
+        if let Theme::Runtime { dir, .. } = self {
+            let static_dir = dir.join("static");
+            if static_dir.is_dir() {
+                outputs.copy_dir(&static_dir, Path::new(output_dir))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::generator::Generator;
+    use crate::models::{Article, HtmlBody, Section, Site, TopPage};
+    use crate::test_utils;
+
+    fn article() -> Article {
+        Article {
+            body: HtmlBody("<p>本文</p>".to_string()),
+            excerpt: HtmlBody("<p>本文</p>".to_string()),
+            extra: [("mood".to_string(), "眠い".to_string())].into(),
+            ..test_utils::article("テーマ")
+        }
+    }
+
+    #[test]
+    fn test_load_builtin_theme() {
+        let result = Theme::load(None).unwrap();
+
+        assert!(matches!(result, Theme::Builtin));
+    }
+
+    #[test]
+    fn test_load_missing_theme() {
+        let result = Theme::load(Some("missing"));
+
+        assert!(matches!(result, Err(ChabloError::ThemeNotFound(_))));
+    }
+
+    #[test]
+    fn test_render_with_runtime_theme() {
+        let mut theme = Theme::from_dir(PathBuf::from("tests/fixtures/themes/simple")).unwrap();
+        theme.set_assets(AssetManifest(
+            [(
+                "css/theme.css".to_string(),
+                "css/theme.0123abcd.css".to_string(),
+            )]
+            .into(),
+        ));
+        let site = Site::new(&Config::default(), &[]);
+
+        let result = article().generate(&site, &theme).unwrap();
+
+        assert!(result.contains(r#"<article class="simple">"#));
+        assert!(result.contains("<footer>chansuke / 眠い</footer>"));
+        assert!(result.contains("<h1>テーマ</h1>"));
+        assert!(result.contains("<p>本文</p>"));
+        assert!(result.contains(r#"href="/css/theme.0123abcd.css""#));
+    }
+
+    #[test]
+    fn test_render_falls_back_to_builtin() {
+        let theme = Theme::from_dir(PathBuf::from("tests/fixtures/themes/simple")).unwrap();
+        let toppage = TopPage {
+            section: "diary".to_string(),
+            articles: vec![article()],
+        };
+
+        let site = Site::new(&Config::default(), &[]);
+
+        let result = toppage.generate(&site, &theme).unwrap();
+
+        assert!(result.contains(r#"<ol class="articles">"#));
+    }
+
+    #[test]
+    fn test_render_with_missing_template() {
+        let mut site = Site::new(&Config::default(), &[]);
+        site.sections = vec![Section {
+            name: "notes".to_string(),
+            template: "note.html".to_string(),
+            ..Section::default()
+        }];
+        let mut article = article();
+        article.section = "notes".to_string();
+        let theme = Theme::from_dir(PathBuf::from("tests/fixtures/themes/simple")).unwrap();
+
+        let builtin = article.clone().generate(&site, &Theme::Builtin);
+        let runtime = article.generate(&site, &theme);
+
+        assert!(matches!(builtin, Err(ChabloError::TemplateNotFound(name)) if name == "note.html"));
+        assert!(matches!(runtime, Err(ChabloError::TemplateNotFound(name)) if name == "note.html"));
+    }
+}
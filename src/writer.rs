@@ -1,51 +1,399 @@
 //! Render it's files
-use std::fs::File;
-use std::io::Write;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
 
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
 use crate::errors::ChabloError;
 
-/// Write down content into file
-pub fn write(template: &str, path: &str) -> Result<(), ChabloError> {
-    let mut file = File::create(path)?;
-    file.write_all(template.as_bytes())?;
+/// Copies made by the last build, so that unchanged files are not copied again
+pub const COPY_CACHE_PATH: &str = ".cache/copies.json";
+
+/// Distinguishes the temporary files of concurrent writes
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Write down content into file, creating its parent directories. The content goes to a
+/// temporary file renamed over `path`, so that readers never see a half-written file.
+/// An identical file is left untouched to keep its modification time.
+pub fn write<C: AsRef<[u8]>>(content: C, path: &str) -> Result<(), ChabloError> {
+    let path = Path::new(path);
+    let content = content.as_ref();
+    if !is_identical(path, content) {
+        replace(path, |file| file.write_all(content))?;
+    }
 
     Ok(())
 }
 
+/// Fill a temporary file in the directory of `path`, then rename it over `path`. A
+/// symbolic link at `path` is replaced rather than written through.
+fn replace(path: &Path, fill: impl FnOnce(&mut File) -> io::Result<()>) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = dir.join(format!(
+        ".{file_name}.{}-{}.tmp",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = File::create(&temp_path)
+        .and_then(|mut file| fill(&mut file).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+fn is_identical(path: &Path, content: &[u8]) -> bool {
+    let same_length =
+        fs::metadata(path).is_ok_and(|metadata| metadata.len() == content.len() as u64);
+
+    same_length && fs::read(path).is_ok_and(|existing| existing == content)
+}
+
+/// Size and modification time of a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    len: u64,
+    modified: SystemTime,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> io::Result<Stamp> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(Stamp {
+            len: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+}
+
+/// Copy of a file, with the stamps of both ends at the end of the build
+#[derive(Debug, Serialize, Deserialize)]
+struct CopyRecord {
+    source: PathBuf,
+    dest: PathBuf,
+    source_stamp: Stamp,
+    dest_stamp: Stamp,
+}
+
+/// Stamps of the source and destination of a copy, keyed by both paths
+type Copies = BTreeMap<(PathBuf, PathBuf), (Stamp, Stamp)>;
+
+/// Files written or kept by a build, so that the others can be removed as stale
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Outputs {
+    paths: BTreeSet<PathBuf>,
+    /// Copies made by the last build
+    cached_copies: Copies,
+    /// Copies made or kept by this build
+    copies: Copies,
+}
+
+impl Outputs {
+    /// Start a build remembering the copies saved by `save_copies`, a missing or
+    /// unreadable cache meaning that every file is copied again
+    pub fn with_copy_cache(path: &Path) -> Outputs {
+        let records: Vec<CopyRecord> = fs::read(path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+
+        Outputs {
+            cached_copies: records
+                .into_iter()
+                .map(|r| ((r.source, r.dest), (r.source_stamp, r.dest_stamp)))
+                .collect(),
+            ..Outputs::default()
+        }
+    }
+
+    /// Save the copies of this build. Destinations are stamped as they are now, so that
+    /// one overwritten after its copy, like a processed image, is kept next time.
+    pub fn save_copies(&self, path: &Path) -> Result<(), ChabloError> {
+        let records: Vec<CopyRecord> = self
+            .copies
+            .iter()
+            .filter_map(|((source, dest), (source_stamp, _))| {
+                Some(CopyRecord {
+                    source: source.clone(),
+                    dest: dest.clone(),
+                    source_stamp: *source_stamp,
+                    dest_stamp: Stamp::of(dest).ok()?,
+                })
+            })
+            .collect();
+
+        write(serde_json::to_string(&records)?, &path.to_string_lossy())
+    }
+
+    /// Write down content into file like `write`, recording it as an output
+    pub fn write<C: AsRef<[u8]>>(&mut self, content: C, path: &str) -> Result<(), ChabloError> {
+        write(content, path)?;
+        self.mark(Path::new(path));
+
+        Ok(())
+    }
+
+    /// Copy a file like `write`, unless neither `src` nor `dest` changed since it was
+    /// last copied
+    pub fn copy_file(&mut self, src: &Path, dest: &Path) -> Result<(), ChabloError> {
+        let key = (src.to_path_buf(), dest.to_path_buf());
+        let source_stamp = Stamp::of(src)?;
+        let up_to_date = self
+            .copies
+            .get(&key)
+            .or_else(|| self.cached_copies.get(&key))
+            .is_some_and(|&(source, dest_stamp)| {
+                source == source_stamp && Stamp::of(dest).ok() == Some(dest_stamp)
+            });
+        if !up_to_date {
+            let mut source = File::open(src)?;
+            replace(dest, |file| io::copy(&mut source, file).map(|_| ()))?;
+        }
+        self.copies.insert(key, (source_stamp, Stamp::of(dest)?));
+        self.mark(dest);
+
+        Ok(())
+    }
+
+    /// Copy every file under `src` into `dest`, keeping the directory layout. Files
+    /// already copied by an earlier build are skipped.
+    pub fn copy_dir(&mut self, src: &Path, dest: &Path) -> Result<(), ChabloError> {
+        fs::create_dir_all(dest)?;
+
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            let target = dest.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                self.copy_dir(&entry.path(), &target)?;
+            } else {
+                self.copy_file(&entry.path(), &target)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record a file as an output, e.g. one reused from an earlier build
+    pub fn mark(&mut self, path: &Path) {
+        if let (true, Some(path)) = (fs::symlink_metadata(path).is_ok(), canonical_path(path)) {
+            self.paths.insert(path);
+        }
+    }
+
+    /// Whether the file was recorded
+    pub fn contains(&self, path: &Path) -> bool {
+        canonical_path(path).is_some_and(|path| self.paths.contains(&path))
+    }
+
+    /// Canonical paths of the recorded files
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.paths.iter()
+    }
+}
+
+/// Absolute path with its parent directory resolved. Unlike `fs::canonicalize`, a
+/// symbolic link is not replaced with its target.
+fn canonical_path(path: &Path) -> Option<PathBuf> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    Some(fs::canonicalize(dir).ok()?.join(path.file_name()?))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::temp_dir;
     use pretty_assertions::assert_eq;
-    use std::fs;
 
     #[test]
     fn test_write_content_to_file_ok() {
         let content = "test test test".to_string();
-        let path = "tests/fixtures/test.html";
+        let dir = temp_dir("write");
+        let path = dir.path().join("test.html");
 
-        write(&content, path).unwrap();
+        write(&content, &path.to_string_lossy()).unwrap();
 
         let result = fs::read_to_string(path).unwrap();
         let expected_result = "test test test";
 
         assert_eq!(result, expected_result);
-
-        fs::remove_file(path).unwrap();
     }
 
     #[test]
     fn test_write_content_to_file_metadata_ok() {
         let content = "testing file type".to_string();
-        let path = "tests/fixtures/test_metadata.html";
+        let dir = temp_dir("write_metadata");
+        let path = dir.path().join("test_metadata.html");
 
-        write(&content, path).unwrap();
+        write(&content, &path.to_string_lossy()).unwrap();
 
         let metadata = fs::metadata(path).unwrap();
 
         assert!(metadata.is_file());
+    }
+
+    #[test]
+    fn test_copy_dir_ok() {
+        let src = Path::new("tests/fixtures/themes/simple/static");
+        let dir = temp_dir("copy_output");
+        let dest = dir.path();
+
+        Outputs::default().copy_dir(src, dest).unwrap();
+
+        let result = fs::read_to_string(dest.join("css/theme.css")).unwrap();
+        let expected_result = fs::read_to_string(src.join("css/theme.css")).unwrap();
+
+        assert_eq!(result, expected_result);
+    }
+
+    #[test]
+    fn test_copy_dir_skips_up_to_date_files() {
+        let dir = temp_dir("copy_dir");
+        let (src, dest) = (dir.path().join("src"), dir.path().join("dest"));
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        let mut outputs = Outputs::default();
+
+        outputs.copy_dir(&src, &dest).unwrap();
+        let copied = fs::metadata(dest.join("a.txt"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        outputs.copy_dir(&src, &dest).unwrap();
+        let skipped = fs::metadata(dest.join("a.txt"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        fs::write(src.join("a.txt"), "changed").unwrap();
+        outputs.copy_dir(&src, &dest).unwrap();
+        let result = fs::read_to_string(dest.join("a.txt")).unwrap();
+
+        assert_eq!(copied, skipped);
+        assert_eq!(result, "changed");
+    }
+
+    #[test]
+    fn test_copy_file_with_older_source_of_same_size() {
+        let dir = temp_dir("copy_older");
+        let dir = dir.path();
+        let (src, dest) = (dir.join("src.txt"), dir.join("dest.txt"));
+        fs::write(&src, "a").unwrap();
+        let mut outputs = Outputs::default();
+
+        outputs.copy_file(&src, &dest).unwrap();
+        // Like `cp -p` of an older file, which keeps its modification time
+        fs::write(&src, "b").unwrap();
+        let older = SystemTime::now() - std::time::Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(&src)
+            .unwrap()
+            .set_modified(older)
+            .unwrap();
+        outputs.copy_file(&src, &dest).unwrap();
+        let result = fs::read_to_string(&dest).unwrap();
+
+        assert_eq!(result, "b");
+    }
+
+    #[test]
+    fn test_copy_file_keeps_destination_overwritten_after_copy() {
+        let dir = temp_dir("copy_overwritten");
+        let dir = dir.path();
+        let (src, dest) = (dir.join("photo.jpg"), dir.join("public.jpg"));
+        let cache = dir.join("copies.json");
+        fs::write(&src, "original").unwrap();
+        let mut outputs = Outputs::with_copy_cache(&cache);
+        outputs.copy_file(&src, &dest).unwrap();
+        // Processed after its copy, e.g. with its metadata stripped
+        fs::write(&dest, "processed").unwrap();
+        outputs.save_copies(&cache).unwrap();
+
+        let mut outputs = Outputs::with_copy_cache(&cache);
+        outputs.copy_file(&src, &dest).unwrap();
+        let kept = fs::read_to_string(&dest).unwrap();
+        let marked = outputs.contains(&dest);
+        let mut outputs = Outputs::default();
+        outputs.copy_file(&src, &dest).unwrap();
+        let without_cache = fs::read_to_string(&dest).unwrap();
+
+        assert_eq!(kept, "processed");
+        assert!(marked);
+        assert_eq!(without_cache, "original");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_file_replaces_symlink() {
+        let dir = temp_dir("copy_symlink");
+        let dir = dir.path();
+        let (src, dest, outside) = (dir.join("a.css"), dir.join("b.css"), dir.join("outside"));
+        fs::write(&src, "copied").unwrap();
+        fs::write(&outside, "outside").unwrap();
+        std::os::unix::fs::symlink(&outside, &dest).unwrap();
+
+        Outputs::default().copy_file(&src, &dest).unwrap();
+        let is_link = fs::symlink_metadata(&dest)
+            .unwrap()
+            .file_type()
+            .is_symlink();
+        let files = fs::read_dir(dir).unwrap().count();
+
+        assert!(!is_link);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "copied");
+        assert_eq!(fs::read_to_string(&outside).unwrap(), "outside");
+        // No temporary file is left behind
+        assert_eq!(files, 3);
+    }
+
+    #[test]
+    fn test_write_creates_parent_directories() {
+        let dir = temp_dir("write_parents");
+        let dir = dir.path();
+        let path = dir.join("a/b/c.html");
+
+        write("nested", &path.to_string_lossy()).unwrap();
+        let result = fs::read_to_string(&path).unwrap();
+        let files: Vec<_> = fs::read_dir(dir.join("a/b")).unwrap().collect();
+
+        assert_eq!(result, "nested");
+        // No temporary file is left behind
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn test_write_skips_identical_content() {
+        let dir = temp_dir("write_identical");
+        let dir = dir.path();
+        let path = dir.join("page.html");
+        let path_str = path.to_string_lossy().to_string();
+
+        write("same", &path_str).unwrap();
+        let written = fs::metadata(&path).unwrap().modified().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        write("same", &path_str).unwrap();
+        let skipped = fs::metadata(&path).unwrap().modified().unwrap();
+        write("changed", &path_str).unwrap();
+        let result = fs::read_to_string(&path).unwrap();
 
-        fs::remove_file(path).unwrap();
+        assert_eq!(written, skipped);
+        assert_eq!(result, "changed");
     }
 }
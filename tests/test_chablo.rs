@@ -13,6 +13,17 @@ fn test_chablo_ok() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn test_build_fixture_site_without_broken_links() -> Result<(), Box<dyn Error>> {
+    let mut bin = bin();
+    bin.arg("build").current_dir("tests/fixtures/site");
+    bin.assert()
+        .success()
+        .stderr(predicate::str::contains("Broken link").not());
+
+    Ok(())
+}
+
 #[test]
 fn test_chablo_error() -> Result<(), Box<dyn Error>> {
     let mut bin = bin();
@@ -29,6 +40,19 @@ fn test_chablo_error() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn test_check_links_with_too_many_cache_days() -> Result<(), Box<dyn Error>> {
+    let mut bin = bin();
+    bin.args(["check-links", "--cache-days", &i64::MAX.to_string()]);
+
+    bin.assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("invalid value"));
+
+    Ok(())
+}
+
 fn bin() -> Command {
     Command::cargo_bin("chablo").unwrap()
 }